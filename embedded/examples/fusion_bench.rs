@@ -0,0 +1,105 @@
+//! Measures the cycles spent in one `update_imu` of the Madgwick filter for each numeric type
+//! and prints them over USART2 (115200 baud).
+//!
+//! The numbers come from the F446, which has a single precision FPU. They are indicative only
+//! for a Cortex-M0 node, which runs f32 in software and lacks a single cycle 64 bit multiply.
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::{fmt::Write, ptr};
+
+use cortex_m::peripheral::DWT;
+use cortex_m_rt::entry;
+
+use stm32f4xx_hal as hal;
+
+use hal::{
+    dwt::DwtExt,
+    prelude::*,
+    serial::Serial,
+    stm32::{CorePeripherals, Peripherals},
+};
+
+use embedded::handler::{fixed::Q16, madgwick::Estimated, real::Real};
+
+const HCLK: u32 = 180_000_000; // Hertz
+const ITERATIONS: u32 = 1000;
+
+// a slowly rotating, slightly tilted sample so that every branch of the update runs
+const ACC: [f32; 3] = [0.12, -0.34, 9.7];
+const GYR: [f32; 3] = [1.5, -0.8, 0.3];
+
+fn cycles_per_update<N: Real>() -> u32 {
+    let mut est = Estimated::<N>::new(N::from_f32(0.1), N::from_f32(100.0));
+    est.set_bias_gain(N::from_f32(0.005));
+    let acc = [
+        N::from_f32(ACC[0]),
+        N::from_f32(ACC[1]),
+        N::from_f32(ACC[2]),
+    ];
+    let gyr = [
+        N::from_f32(GYR[0]),
+        N::from_f32(GYR[1]),
+        N::from_f32(GYR[2]),
+    ];
+
+    // the volatile reads keep the compiler from hoisting the samples out of the loop or
+    // dropping the updates as unused
+    let start = DWT::cycle_count();
+    for _ in 0..ITERATIONS {
+        let [ax, ay, az] = unsafe { ptr::read_volatile(&acc) };
+        let [gx, gy, gz] = unsafe { ptr::read_volatile(&gyr) };
+        est.update_imu(ax, ay, az, gx, gy, gz);
+    }
+    let elapsed = DWT::cycle_count().wrapping_sub(start);
+    unsafe { ptr::read_volatile(&est.get_quaternion()) };
+
+    elapsed / ITERATIONS
+}
+
+#[entry]
+fn main() -> ! {
+    if let (Some(peripherals), Some(core_peripherals)) =
+        (Peripherals::take(), CorePeripherals::take())
+    {
+        let rcc = peripherals.RCC.constrain();
+        let clock = rcc
+            .cfgr
+            .use_hse(8.mhz())
+            .hclk(HCLK.hz())
+            .pclk1(45.mhz())
+            .pclk2(90.mhz())
+            .sysclk(HCLK.hz())
+            .freeze();
+
+        let _dwt = core_peripherals.DWT.constrain(core_peripherals.DCB, clock);
+
+        let gpioa = peripherals.GPIOA.split();
+        let usart = Serial::usart2(
+            peripherals.USART2,
+            (
+                gpioa.pa2.into_alternate_af7(),
+                gpioa.pa3.into_alternate_af7(),
+            ),
+            hal::serial::config::Config::default().baudrate(hal::time::Bps(115200)),
+            clock,
+        )
+        .unwrap();
+        let (mut tx, _rx) = usart.split();
+
+        let f32_cycles = cycles_per_update::<f32>();
+        let f64_cycles = cycles_per_update::<f64>();
+        let q16_cycles = cycles_per_update::<Q16>();
+
+        writeln!(tx, "update_imu cycles (avg of {})\r", ITERATIONS).ok();
+        writeln!(tx, "  f32: {}\r", f32_cycles).ok();
+        writeln!(tx, "  f64: {}\r", f64_cycles).ok();
+        writeln!(tx, "  Q16: {}\r", q16_cycles).ok();
+    }
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,100 @@
+//! Prints the die temperature and the raw gyro rate over USART2 (115200 baud) once a second,
+//! as `temperature gx gy gz` lines for `gyro_temp_fit`.
+//!
+//! Start it with the board cold and lying still, and record until the temperature settles.
+#![no_main]
+#![no_std]
+
+extern crate panic_halt;
+
+use core::fmt::Write;
+
+use cortex_m_rt::entry;
+
+use stm32f4xx_hal as hal;
+
+use hal::{
+    delay::Delay,
+    i2c::I2c,
+    prelude::*,
+    serial::Serial,
+    stm32::{CorePeripherals, Peripherals},
+};
+
+use embedded::handler::{bmx055::IMU, madgwick::Estimated, transport::I2cTransport};
+
+const HCLK: u32 = 180_000_000; // Hertz
+const SAMPLE_MS: u32 = 10;
+const SAMPLES_PER_LINE: u32 = 100;
+
+#[entry]
+fn main() -> ! {
+    let peripherals = Peripherals::take().unwrap();
+    let core_peripherals = CorePeripherals::take().unwrap();
+    let rcc = peripherals.RCC.constrain();
+    let clock = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .hclk(HCLK.hz())
+        .pclk1(45.mhz())
+        .pclk2(90.mhz())
+        .sysclk(HCLK.hz())
+        .freeze();
+
+    let mut delay = Delay::new(core_peripherals.SYST, clock);
+
+    let gpioa = peripherals.GPIOA.split();
+    let usart = Serial::usart2(
+        peripherals.USART2,
+        (
+            gpioa.pa2.into_alternate_af7(),
+            gpioa.pa3.into_alternate_af7(),
+        ),
+        hal::serial::config::Config::default().baudrate(hal::time::Bps(115200)),
+        clock,
+    )
+    .unwrap();
+    let (mut tx, _rx) = usart.split();
+
+    let gpiob = peripherals.GPIOB.split();
+    let i2c = I2c::i2c1(
+        peripherals.I2C1,
+        (
+            gpiob.pb8.into_alternate_af4_open_drain(),
+            gpiob.pb9.into_alternate_af4_open_drain(),
+        ),
+        hal::time::KiloHertz(400),
+        clock,
+    );
+    let mut imu = IMU::new(I2cTransport::new(i2c), Estimated::new(0.1, 100.0));
+    imu.configure(&mut delay, 10).unwrap();
+
+    writeln!(tx, "# temperature gx gy gz\r").ok();
+    loop {
+        let mut temperature = 0.0;
+        let mut sum = [0.0_f32; 3];
+        let mut count = 0;
+        for _ in 0..SAMPLES_PER_LINE {
+            if imu.update().is_ok() {
+                temperature += imu.get_temperature();
+                for (s, g) in sum.iter_mut().zip(imu.get_raw_gyr().iter()) {
+                    *s += g;
+                }
+                count += 1;
+            }
+            delay.delay_ms(SAMPLE_MS);
+        }
+        if count > 0 {
+            let n = count as f32;
+            writeln!(
+                tx,
+                "{:.2} {:.4} {:.4} {:.4}\r",
+                temperature / n,
+                sum[0] / n,
+                sum[1] / n,
+                sum[2] / n
+            )
+            .ok();
+        }
+    }
+}
@@ -1,4 +1,15 @@
+pub mod accelerometer;
+pub mod backup;
 pub mod bmx055;
-pub mod madgwick;
+pub mod fifo;
+pub mod gyroscope;
+pub mod interrupt;
+pub mod magnetometer;
 pub mod potentio;
 pub mod serial;
+pub mod transport;
+
+pub use fusion::{
+    calibration, filter, fixed, madgwick, mahony, mekf, motion, quaternion, real, snapshot,
+    stationary, tare,
+};
@@ -0,0 +1,246 @@
+//! Register layout and settings of the BMX055 accelerometer (the BMA280 core).
+
+use super::interrupt::{IntPin, IntSource, PUSH_PULL_ACTIVE_HIGH};
+
+pub const CHIP_ID: u8 = 0xFA;
+
+pub const REG_CHIP_ID: u8 = 0x00;
+pub const REG_DATA: u8 = 0x02;
+pub const REG_TEMP: u8 = 0x08;
+pub const REG_RANGE: u8 = 0x0F;
+pub const REG_BW: u8 = 0x10;
+pub const REG_LPW: u8 = 0x11;
+pub const REG_INT_EN_1: u8 = 0x17;
+pub const REG_INT_MAP_1: u8 = 0x1A;
+pub const REG_INT_OUT_CTRL: u8 = 0x20;
+pub const REG_FIFO_CONFIG_0: u8 = 0x30;
+
+/// Die temperature at a reading of zero, in degrees Celsius.
+const TEMP_CENTER: f32 = 23.0;
+/// Degrees Celsius per LSB of the temperature register.
+const TEMP_RESOLUTION: f32 = 0.5;
+
+/// Frames the FIFO holds.
+pub const FIFO_FRAMES: usize = 32;
+/// Largest FIFO watermark level.
+pub const FIFO_WATERMARK_MAX: u8 = 0x3F;
+
+/// Full scale of the 12 bit output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccRange {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccRange {
+    fn bits(self) -> u8 {
+        match self {
+            AccRange::G2 => 0x03,
+            AccRange::G4 => 0x05,
+            AccRange::G8 => 0x08,
+            AccRange::G16 => 0x0C,
+        }
+    }
+
+    /// Sensitivity (g/LSB), the full scale spread over 2048 counts each way.
+    pub fn resolution(self) -> f32 {
+        let full_scale = match self {
+            AccRange::G2 => 2.0,
+            AccRange::G4 => 4.0,
+            AccRange::G8 => 8.0,
+            AccRange::G16 => 16.0,
+        };
+        full_scale / 2048.0
+    }
+}
+
+/// Filter bandwidth, the output data rate being twice as high.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccBandwidth {
+    Hz7_81,
+    Hz15_63,
+    Hz31_25,
+    Hz62_5,
+    Hz125,
+    Hz250,
+    Hz500,
+    Hz1000,
+}
+
+impl AccBandwidth {
+    fn bits(self) -> u8 {
+        match self {
+            AccBandwidth::Hz7_81 => 0x08,
+            AccBandwidth::Hz15_63 => 0x09,
+            AccBandwidth::Hz31_25 => 0x0A,
+            AccBandwidth::Hz62_5 => 0x0B,
+            AccBandwidth::Hz125 => 0x0C,
+            AccBandwidth::Hz250 => 0x0D,
+            AccBandwidth::Hz500 => 0x0E,
+            AccBandwidth::Hz1000 => 0x0F,
+        }
+    }
+
+    /// Narrowest bandwidth whose output data rate is at least `odr` (Hz).
+    pub const fn for_odr(odr: u32) -> Self {
+        if odr <= 15 {
+            AccBandwidth::Hz7_81
+        } else if odr <= 31 {
+            AccBandwidth::Hz15_63
+        } else if odr <= 62 {
+            AccBandwidth::Hz31_25
+        } else if odr <= 125 {
+            AccBandwidth::Hz62_5
+        } else if odr <= 250 {
+            AccBandwidth::Hz125
+        } else if odr <= 500 {
+            AccBandwidth::Hz250
+        } else if odr <= 1000 {
+            AccBandwidth::Hz500
+        } else {
+            AccBandwidth::Hz1000
+        }
+    }
+
+    /// Output data rate (Hz).
+    pub fn odr(self) -> f32 {
+        let bandwidth = match self {
+            AccBandwidth::Hz7_81 => 7.8125,
+            AccBandwidth::Hz15_63 => 15.625,
+            AccBandwidth::Hz31_25 => 31.25,
+            AccBandwidth::Hz62_5 => 62.5,
+            AccBandwidth::Hz125 => 125.0,
+            AccBandwidth::Hz250 => 250.0,
+            AccBandwidth::Hz500 => 500.0,
+            AccBandwidth::Hz1000 => 1000.0,
+        };
+        2.0 * bandwidth
+    }
+}
+
+/// Power mode. Low power sleeps 0.5 ms between samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccPower {
+    Normal,
+    LowPower,
+    Suspend,
+    DeepSuspend,
+}
+
+impl AccPower {
+    fn bits(self) -> u8 {
+        match self {
+            AccPower::Normal => 0x00,
+            AccPower::DeepSuspend => 0x20,
+            AccPower::LowPower => 0x40,
+            AccPower::Suspend => 0x80,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccSettings {
+    pub range: AccRange,
+    pub bandwidth: AccBandwidth,
+    pub power: AccPower,
+}
+
+impl AccSettings {
+    /// Register and value pairs applying these settings.
+    pub fn registers(&self) -> [(u8, u8); 3] {
+        [
+            (REG_RANGE, self.range.bits()),
+            (REG_BW, self.bandwidth.bits()),
+            (REG_LPW, self.power.bits()),
+        ]
+    }
+}
+
+impl Default for AccSettings {
+    fn default() -> Self {
+        AccSettings {
+            range: AccRange::G2,
+            bandwidth: AccBandwidth::Hz7_81,
+            power: AccPower::Normal,
+        }
+    }
+}
+
+/// Register and value pairs raising `source` on `pin`, or disabling the interrupts.
+pub fn interrupt_registers(interrupt: Option<(IntSource, IntPin)>) -> [(u8, u8); 3] {
+    let (enable, map) = match interrupt {
+        None => (0x00, 0x00),
+        Some((IntSource::DataReady, IntPin::Int1)) => (0x10, 0x01),
+        Some((IntSource::DataReady, IntPin::Int2)) => (0x10, 0x80),
+        Some((IntSource::FifoWatermark, IntPin::Int1)) => (0x40, 0x02),
+        Some((IntSource::FifoWatermark, IntPin::Int2)) => (0x40, 0x40),
+    };
+    [
+        (REG_INT_OUT_CTRL, PUSH_PULL_ACTIVE_HIGH),
+        (REG_INT_MAP_1, map),
+        (REG_INT_EN_1, enable),
+    ]
+}
+
+/// Die temperature in degrees Celsius from the two's complement `REG_TEMP` reading.
+pub fn temperature(raw: u8) -> f32 {
+    TEMP_CENTER + raw as i8 as f32 * TEMP_RESOLUTION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_registers() {
+        let settings = AccSettings {
+            range: AccRange::G8,
+            bandwidth: AccBandwidth::Hz125,
+            power: AccPower::LowPower,
+        };
+        assert_eq!(
+            settings.registers(),
+            [(REG_RANGE, 0x08), (REG_BW, 0x0C), (REG_LPW, 0x40)]
+        );
+    }
+
+    #[test]
+    fn data_ready_on_int1() {
+        let regs = interrupt_registers(Some((IntSource::DataReady, IntPin::Int1)));
+        assert_eq!(
+            regs,
+            [
+                (REG_INT_OUT_CTRL, 0x05),
+                (REG_INT_MAP_1, 0x01),
+                (REG_INT_EN_1, 0x10)
+            ]
+        );
+    }
+
+    #[test]
+    fn resolution_doubles_with_range() {
+        assert!((AccRange::G2.resolution() - 0.000_977).abs() < 1e-6);
+        assert_eq!(AccRange::G16.resolution(), 8.0 * AccRange::G2.resolution());
+    }
+
+    #[test]
+    fn bandwidth_covers_odr() {
+        assert_eq!(AccBandwidth::for_odr(100), AccBandwidth::Hz62_5);
+        assert_eq!(AccBandwidth::for_odr(125), AccBandwidth::Hz62_5);
+        assert_eq!(AccBandwidth::for_odr(126), AccBandwidth::Hz125);
+        assert_eq!(AccBandwidth::for_odr(1), AccBandwidth::Hz7_81);
+        assert_eq!(AccBandwidth::for_odr(5000), AccBandwidth::Hz1000);
+        for odr in [10, 100, 300, 2000].iter() {
+            assert!(AccBandwidth::for_odr(*odr).odr() >= *odr as f32);
+        }
+    }
+
+    #[test]
+    fn temperature_is_signed_around_23() {
+        assert_eq!(temperature(0x00), 23.0);
+        assert_eq!(temperature(0x0A), 28.0);
+        assert_eq!(temperature(0xF6), 18.0);
+    }
+}
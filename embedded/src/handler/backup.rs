@@ -0,0 +1,44 @@
+use core::ptr;
+
+use stm32f4xx_hal as hal;
+
+use hal::stm32::{PWR, RCC};
+
+const BKPSRAM_BASE: usize = 0x4002_4000;
+
+/// Size of the backup SRAM of the STM32F446.
+pub const SIZE: usize = 4096;
+
+/// Backup SRAM, whose content survives resets as long as VDD or VBAT stays up.
+pub struct BackupSram {
+    _private: (),
+}
+
+impl BackupSram {
+    /// Enables the clock of the backup domain and the backup regulator.
+    pub fn new(pwr: &PWR) -> Self {
+        // RCC has been constrained by the clock setup, only the enable bits are touched here
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+        rcc.ahb1enr.modify(|_, w| w.bkpsramen().set_bit());
+        pwr.csr.modify(|_, w| w.bre().set_bit());
+        while pwr.csr.read().brr().bit_is_clear() {}
+
+        BackupSram { _private: () }
+    }
+
+    /// Copies `buf.len()` bytes from the start of the backup SRAM.
+    pub fn read(&self, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().take(SIZE).enumerate() {
+            *byte = unsafe { ptr::read_volatile((BKPSRAM_BASE + i) as *const u8) };
+        }
+    }
+
+    /// Copies `data` to the start of the backup SRAM.
+    pub fn write(&mut self, data: &[u8]) {
+        for (i, byte) in data.iter().take(SIZE).enumerate() {
+            unsafe { ptr::write_volatile((BKPSRAM_BASE + i) as *mut u8, *byte) };
+        }
+    }
+}
@@ -1,23 +1,40 @@
-use super::madgwick;
+use super::accelerometer::{self as acc, AccSettings};
+use super::calibration::{AccCalibration, GyroTempModel, MagCalibration};
+use super::fifo::{self, FifoConfig, FifoReport, FifoStatus};
+use super::filter::OrientationFilter;
+use super::gyroscope::{self as gyr, GyrSettings};
+use super::interrupt::InterruptConfig;
+use super::magnetometer::{self as mag, MagRaw, MagSettings, MagTrim};
+use super::motion;
+use super::quaternion::Quaternion;
+use super::snapshot::{Reader, Snapshot, Writer};
+use super::stationary::StationaryDetector;
+use super::tare::{Tare, TareMode};
 
-use stm32f4xx_hal as hal;
+use super::transport::{Chip, Transport};
 
-use hal::prelude::{
-    _embedded_hal_blocking_delay_DelayMs, _embedded_hal_blocking_i2c_Write,
-    _embedded_hal_blocking_i2c_WriteRead,
-};
-use hal::{delay::Delay, i2c::*};
+use embedded_hal::blocking::delay::DelayMs;
 
-const SETTINGS_ACC: [u8; 6] = [0x0F, 0x03, 0x10, 0x08, 0x11, 0x00];
-const SETTINGS_GYR: [u8; 6] = [0x0F, 0x04, 0x10, 0x07, 0x11, 0x00];
-// const SETTINGS_MAG: [u8; 10] = [0x4B, 0x01, 0x4C, 0x00, 0x4E, 0x84, 0x51, 0x04, 0x52, 0x0F];
+const GRAVITY: f32 = 9.80665; // m/s^2
+const DEG_TO_RAD: f32 = 0.0174533;
+const STATIONARY_BIAS_RATE: f32 = 10.0; // 1/sec, the residual rate taken at rest per second
 
-const ADDR_ACC: u8 = 0x19;
-const ADDR_GYR: u8 = 0x69;
-// const ADDR_MAG: u8 = 0x13;
+/// Attempts of a register access, or polls of a chip coming up, before giving up.
+const RETRIES: u32 = 3;
 
-pub struct IMU<T, U> {
-    dev: I2c<T, U>,
+#[derive(Debug)]
+pub enum Bmx055Error<E> {
+    /// A bus transfer failed `RETRIES` times in a row, with the last error.
+    Bus(E),
+    /// The chip answering as `chip` is not the expected BMX055 core.
+    WrongChipId { chip: Chip, found: u8 },
+    /// The magnetometer did not answer within `RETRIES` power-up times. `configure` does not
+    /// fail on it, the magnetometer being optional, see `has_mag`.
+    Timeout,
+}
+
+pub struct IMU<B, F> {
+    bus: B,
     x_acc: f32,
     y_acc: f32,
     z_acc: f32,
@@ -27,17 +44,35 @@ pub struct IMU<T, U> {
     x_gyr_init: f32,
     y_gyr_init: f32,
     z_gyr_init: f32,
-    pub imu_data: madgwick::Estimated,
+    gyr_raw: [f32; 3],
+    gyr_temp_model: Option<GyroTempModel>,
+    temperature: f32,
+    x_mag: f32,
+    y_mag: f32,
+    z_mag: f32,
+    acc_settings: AccSettings,
+    gyr_settings: GyrSettings,
+    fifo: FifoConfig,
+    fifo_overruns: u32,
+    interrupts: Option<InterruptConfig>,
+    mag_trim: MagTrim,
+    mag_settings: MagSettings,
+    mag_calibration: Option<MagCalibration>,
+    mag_present: bool,
+    acc_calibration: AccCalibration,
+    tare: Tare,
+    stationary: StationaryDetector,
+    pub imu_data: F,
 }
 
-impl<T, U> IMU<T, U>
+impl<B, F> IMU<B, F>
 where
-    U: Pins<T>,
-    I2c<T, U>: _embedded_hal_blocking_i2c_WriteRead + _embedded_hal_blocking_i2c_Write,
+    B: Transport,
+    F: OrientationFilter,
 {
-    pub fn new(i2c: I2c<T, U>, gain: f32, freq: f32) -> Self {
+    pub fn new(bus: B, filter: F) -> Self {
         IMU {
-            dev: i2c,
+            bus,
             x_acc: 0.0,
             y_acc: 0.0,
             z_acc: 0.0,
@@ -47,110 +82,1255 @@ where
             x_gyr_init: 0.0,
             y_gyr_init: 0.0,
             z_gyr_init: 0.0,
-            imu_data: madgwick::Estimated::new(gain, freq),
+            gyr_raw: [0.0; 3],
+            gyr_temp_model: None,
+            temperature: acc::temperature(0),
+            x_mag: 0.0,
+            y_mag: 0.0,
+            z_mag: 0.0,
+            acc_settings: AccSettings::default(),
+            gyr_settings: GyrSettings::default(),
+            fifo: FifoConfig::default(),
+            fifo_overruns: 0,
+            interrupts: None,
+            mag_trim: MagTrim::default(),
+            mag_settings: MagSettings::default(),
+            mag_calibration: None,
+            mag_present: false,
+            acc_calibration: AccCalibration::default(),
+            tare: Tare::new(),
+            stationary: StationaryDetector::default(),
+            imu_data: filter,
+        }
+    }
+
+    /// Checks the chip IDs and writes the sensor settings, without touching the offsets or the
+    /// filter. A magnetometer that is missing or does not come up is recorded as absent instead
+    /// of failing, the fusion then going on without it.
+    pub fn configure(
+        &mut self,
+        delay: &mut impl DelayMs<u32>,
+        delay_ms: u32,
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        self.check_chip_id(Chip::Acc, acc::REG_CHIP_ID, acc::CHIP_ID)?;
+        self.check_chip_id(Chip::Gyr, gyr::REG_CHIP_ID, gyr::CHIP_ID)?;
+
+        for (reg, value) in self.acc_settings.registers().iter() {
+            self.write_register(Chip::Acc, *reg, *value)?;
+        }
+        let watermark = self.fifo.watermark.min(acc::FIFO_WATERMARK_MAX);
+        self.write_register(Chip::Acc, acc::REG_FIFO_CONFIG_0, watermark)?;
+        self.write_register(Chip::Acc, fifo::REG_CONFIG_1, self.fifo.config_1())?;
+        let interrupt = self
+            .interrupts
+            .and_then(|config| config.acc.map(|pin| (config.source, pin)));
+        for (reg, value) in acc::interrupt_registers(interrupt).iter() {
+            self.write_register(Chip::Acc, *reg, *value)?;
+        }
+        delay.delay_ms(delay_ms);
+
+        for (reg, value) in self.gyr_settings.registers().iter() {
+            self.write_register(Chip::Gyr, *reg, *value)?;
+        }
+        let watermark = self.fifo.watermark.min(gyr::FIFO_WATERMARK_MAX);
+        self.write_register(Chip::Gyr, gyr::REG_FIFO_CONFIG_0, watermark)?;
+        self.write_register(Chip::Gyr, fifo::REG_CONFIG_1, self.fifo.config_1())?;
+        let interrupt = self
+            .interrupts
+            .and_then(|config| config.gyr.map(|pin| (config.source, pin)));
+        for (reg, value) in gyr::interrupt_registers(interrupt).iter() {
+            self.write_register(Chip::Gyr, *reg, *value)?;
+        }
+        delay.delay_ms(delay_ms);
+
+        self.mag_present = self.configure_mag(delay).is_ok();
+        delay.delay_ms(delay_ms);
+        Ok(())
+    }
+
+    /// Range, bandwidth and power mode used by the accelerometer from the next `configure`.
+    pub fn set_acc_settings(&mut self, settings: AccSettings) {
+        self.acc_settings = settings;
+    }
+
+    /// Range, output data rate and power mode used by the gyro from the next `configure`.
+    pub fn set_gyr_settings(&mut self, settings: GyrSettings) {
+        self.gyr_settings = settings;
+    }
+
+    /// FIFO mode and watermark used by the accelerometer and the gyro from the next `configure`.
+    /// Outside bypass mode the samples are to be read with `drain_fifo`.
+    pub fn set_fifo(&mut self, config: FifoConfig) {
+        self.fifo = config;
+    }
+
+    /// Interrupts raised on the INT pins from the next `configure`, none by default. They let
+    /// the reads follow the sensors' own sampling clock instead of a timer.
+    pub fn set_interrupts(&mut self, config: Option<InterruptConfig>) {
+        self.interrupts = config;
+    }
+
+    /// Drains that found frames lost to a full FIFO.
+    pub fn fifo_overruns(&self) -> u32 {
+        self.fifo_overruns
+    }
+
+    /// Repetitions and output data rate used by the magnetometer from the next `configure`.
+    pub fn set_mag_settings(&mut self, settings: MagSettings) {
+        self.mag_settings = settings;
+    }
+
+    /// Whether the magnetometer came up at the latest `configure`.
+    pub fn has_mag(&self) -> bool {
+        self.mag_present
+    }
+
+    /// Hard- and soft-iron correction of the magnetometer. Once set, the corrected field also
+    /// takes part in the fusion to fix the heading.
+    pub fn set_mag_calibration(&mut self, calibration: MagCalibration) {
+        self.mag_calibration = Some(calibration);
+    }
+
+    /// Bias and scale correction of the accelerometer, applied to every reading from now on.
+    pub fn set_acc_calibration(&mut self, calibration: AccCalibration) {
+        self.acc_calibration = calibration;
+    }
+
+    pub fn get_acc_calibration(&self) -> AccCalibration {
+        self.acc_calibration
+    }
+
+    /// Temperature model of the gyro bias, fitted with `gyro_temp_fit`. Once set, the gyro
+    /// offsets only hold what the model leaves of the bias.
+    pub fn set_gyr_temp_model(&mut self, model: GyroTempModel) {
+        self.gyr_temp_model = Some(model);
+    }
+
+    /// Uncalibrated accelerometer reading (m/s^2) averaged over `count` samples, as needed to
+    /// solve a new calibration.
+    pub fn average_acc(
+        &mut self,
+        delay: &mut impl DelayMs<u32>,
+        delay_ms: u32,
+        count: u32,
+    ) -> Result<[f32; 3], Bmx055Error<B::Error>> {
+        let calibration = core::mem::take(&mut self.acc_calibration);
+        let samples = count.max(1);
+        let mut sum = [0.0_f32; 3];
+        for _ in 0..samples {
+            if let Err(e) = self.measure_acc() {
+                self.acc_calibration = calibration;
+                return Err(e);
+            }
+            delay.delay_ms(delay_ms);
+            sum[0] += self.x_acc;
+            sum[1] += self.y_acc;
+            sum[2] += self.z_acc;
         }
+        self.acc_calibration = calibration;
+        Ok([
+            sum[0] / samples as f32,
+            sum[1] / samples as f32,
+            sum[2] / samples as f32,
+        ])
+    }
+
+    /// Powers the magnetometer up, reads its trim registers and starts normal mode.
+    fn configure_mag(
+        &mut self,
+        delay: &mut impl DelayMs<u32>,
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        self.write_mag(mag::REG_POWER, 0x01)?;
+        self.wait_mag_ready(delay)?;
+
+        let mut x1y1 = [0u8; 2];
+        let mut z4x2y2 = [0u8; 4];
+        let mut rest = [0u8; 10];
+        self.read_mag(mag::REG_TRIM_X1Y1, &mut x1y1)?;
+        self.read_mag(mag::REG_TRIM_Z4X2Y2, &mut z4x2y2)?;
+        self.read_mag(mag::REG_TRIM_Z2Z1XYZ1Z3XY2XY1, &mut rest)?;
+        self.mag_trim = MagTrim::from_registers(&x1y1, &z4x2y2, &rest);
+
+        self.write_mag(mag::REG_INT_AXES, mag::INT_AXES)?;
+        for (reg, value) in self.mag_settings.registers().iter() {
+            self.write_mag(*reg, *value)?;
+        }
+        Ok(())
+    }
+
+    /// Polls the magnetometer ID, which it only answers once powered up.
+    fn wait_mag_ready(
+        &mut self,
+        delay: &mut impl DelayMs<u32>,
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        let mut id = [0u8; 1];
+        let mut found = None;
+        for _ in 0..RETRIES {
+            delay.delay_ms(mag::POWER_UP_MS);
+            match self
+                .bus
+                .read_registers(Chip::Mag, mag::REG_CHIP_ID, &mut id)
+            {
+                Ok(()) if id[0] == mag::CHIP_ID => return Ok(()),
+                Ok(()) => found = Some(id[0]),
+                Err(_) => {}
+            }
+        }
+        Err(match found {
+            Some(found) => Bmx055Error::WrongChipId {
+                chip: Chip::Mag,
+                found,
+            },
+            None => Bmx055Error::Timeout,
+        })
+    }
+
+    fn write_mag(&mut self, reg: u8, value: u8) -> Result<(), Bmx055Error<B::Error>> {
+        self.write_register(Chip::Mag, reg, value)
     }
 
-    pub fn initialize(&mut self, delay: &mut Delay, delay_ms: u32, count: u32) {
+    /// Writes one register, trying up to `RETRIES` times.
+    fn write_register(
+        &mut self,
+        chip: Chip,
+        reg: u8,
+        value: u8,
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        let mut attempt = 1;
         loop {
-            match self.dev.write(ADDR_ACC, &SETTINGS_ACC) {
-                Ok(_) => break,
-                Err(_) => continue,
+            match self.bus.write_register(chip, reg, value) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= RETRIES => return Err(Bmx055Error::Bus(e)),
+                Err(_) => attempt += 1,
             }
         }
-        delay.delay_ms(delay_ms);
+    }
+
+    fn read_mag(&mut self, reg: u8, data: &mut [u8]) -> Result<(), Bmx055Error<B::Error>> {
+        self.read_registers(Chip::Mag, reg, data)
+    }
 
+    /// Reads consecutive registers, trying up to `RETRIES` times.
+    fn read_registers(
+        &mut self,
+        chip: Chip,
+        reg: u8,
+        data: &mut [u8],
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        let mut attempt = 1;
         loop {
-            match self.dev.write(ADDR_GYR, &SETTINGS_GYR) {
-                Ok(_) => break,
-                Err(_) => continue,
+            match self.bus.read_registers(chip, reg, data) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= RETRIES => return Err(Bmx055Error::Bus(e)),
+                Err(_) => attempt += 1,
             }
         }
-        delay.delay_ms(delay_ms);
+    }
 
+    fn check_chip_id(
+        &mut self,
+        chip: Chip,
+        reg: u8,
+        expected: u8,
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        let mut id = [0u8; 1];
+        self.read_registers(chip, reg, &mut id)?;
+        if id[0] != expected {
+            return Err(Bmx055Error::WrongChipId { chip, found: id[0] });
+        }
+        Ok(())
+    }
+
+    pub fn initialize(
+        &mut self,
+        delay: &mut impl DelayMs<u32>,
+        delay_ms: u32,
+        count: u32,
+    ) -> Result<(), Bmx055Error<B::Error>> {
+        self.configure(delay, delay_ms)?;
+        self.measure_temp()?;
+
+        // with no count, the gyro offsets are left to the filter's online bias estimation
+        let samples = count.max(1);
+
+        let mut acc_x = 0.0_f32;
+        let mut acc_y = 0.0_f32;
+        let mut acc_z = 0.0_f32;
         let mut offset_x = 0.0_f32;
         let mut offset_y = 0.0_f32;
         let mut offset_z = 0.0_f32;
+        let mut mag = [0.0_f32; 3];
 
-        for _ in 0..count {
-            self.measure_gyr();
+        for _ in 0..samples {
+            self.measure_acc()?;
+            self.measure_gyr()?;
+            self.measure_mag_if_fused()?;
             delay.delay_ms(delay_ms);
+            acc_x += self.x_acc;
+            acc_y += self.y_acc;
+            acc_z += self.z_acc;
             offset_x += self.x_gyr;
             offset_y += self.y_gyr;
             offset_z += self.z_gyr;
+            mag[0] += self.x_mag;
+            mag[1] += self.y_mag;
+            mag[2] += self.z_mag;
+        }
+
+        // start from the averaged direction of gravity and, once the magnetometer is
+        // calibrated, its heading, so that the correction does not swing the yaw into place
+        let mag = if self.mag_fused() { Some(mag) } else { None };
+        self.imu_data.set_initial([acc_x, acc_y, acc_z], mag);
+
+        if count > 0 {
+            let [b_x, b_y, b_z] = self.gyr_temp_bias();
+            self.x_gyr_init = offset_x / samples as f32 - b_x;
+            self.y_gyr_init = offset_y / samples as f32 - b_y;
+            self.z_gyr_init = offset_z / samples as f32 - b_z;
         }
-        offset_x /= count as f32;
-        offset_y /= count as f32;
-        offset_z /= count as f32;
-        self.x_gyr_init = offset_x;
-        self.y_gyr_init = offset_y;
-        self.z_gyr_init = offset_z;
+        Ok(())
     }
 
-    fn measure_acc(&mut self) {
-        let addr = [0x02];
+    fn measure_acc(&mut self) -> Result<(), Bmx055Error<B::Error>> {
         let mut data = [0u8; 6];
-        let coefficient = 0.0096105; // 9.80665(m/s^2) * 0.00098(G/LSB)
-        if let Ok(_) = self.dev.write_read(ADDR_ACC, &addr, &mut data) {
-            self.x_acc = ((data[1] as f32 * 256.0) + (data[0] & 0xF0) as f32) / 16.0;
-            if self.x_acc > 2047.0 {
-                self.x_acc -= 4096.0
-            }
+        self.read_registers(Chip::Acc, acc::REG_DATA, &mut data)?;
+        self.decode_acc(&data);
+        Ok(())
+    }
+
+    fn measure_temp(&mut self) -> Result<(), Bmx055Error<B::Error>> {
+        let mut data = [0u8; 1];
+        self.read_registers(Chip::Acc, acc::REG_TEMP, &mut data)?;
+        self.temperature = acc::temperature(data[0]);
+        Ok(())
+    }
+
+    /// Converts one frame of the data registers or the FIFO.
+    fn decode_acc(&mut self, data: &[u8]) {
+        let coefficient = GRAVITY * self.acc_settings.range.resolution(); // m/s^2/LSB
+        self.x_acc = ((data[1] as f32 * 256.0) + (data[0] & 0xF0) as f32) / 16.0;
+        if self.x_acc > 2047.0 {
+            self.x_acc -= 4096.0
+        }
+
+        self.y_acc = ((data[3] as f32 * 256.0) + (data[2] & 0xF0) as f32) / 16.0;
+        if self.y_acc > 2047.0 {
+            self.y_acc -= 4096.0
+        }
+
+        self.z_acc = ((data[5] as f32 * 256.0) + (data[4] & 0xF0) as f32) / 16.0;
+        if self.z_acc > 2047.0 {
+            self.z_acc -= 4096.0
+        }
+
+        let [x, y, z] = self.acc_calibration.apply([
+            self.x_acc * coefficient,
+            self.y_acc * coefficient,
+            self.z_acc * coefficient,
+        ]);
+        self.x_acc = x;
+        self.y_acc = y;
+        self.z_acc = z;
+    }
+
+    fn measure_gyr(&mut self) -> Result<(), Bmx055Error<B::Error>> {
+        let mut data = [0u8; 6];
+        self.read_registers(Chip::Gyr, gyr::REG_DATA, &mut data)?;
+        self.decode_gyr(&data);
+        Ok(())
+    }
+
+    /// Converts one frame of the data registers or the FIFO.
+    fn decode_gyr(&mut self, data: &[u8]) {
+        let coefficient = self.gyr_settings.range.resolution(); // deg/sec/LSB
+        self.x_gyr = (data[1] as f32 * 256.0) + data[0] as f32;
+        if self.x_gyr > 32767.0 {
+            self.x_gyr -= 65536.0
+        }
+
+        self.y_gyr = (data[3] as f32 * 256.0) + data[2] as f32;
+        if self.y_gyr > 32767.0 {
+            self.y_gyr -= 65536.0
+        }
+
+        self.z_gyr = (data[5] as f32 * 256.0) + data[4] as f32;
+        if self.z_gyr > 32767.0 {
+            self.z_gyr -= 65536.0
+        }
+
+        self.x_gyr *= coefficient;
+        self.y_gyr *= coefficient;
+        self.z_gyr *= coefficient;
+    }
+
+    fn measure_mag(&mut self) -> Result<(), Bmx055Error<B::Error>> {
+        let mut data = [0u8; 8];
+        self.read_registers(Chip::Mag, mag::REG_DATA, &mut data)?;
+        let mut m = self.mag_trim.compensate(&MagRaw::from_registers(&data));
+        if let Some(ref calibration) = self.mag_calibration {
+            m = calibration.apply(m);
+        }
+        let [x, y, z] = m;
+        self.x_mag = x;
+        self.y_mag = y;
+        self.z_mag = z;
+        Ok(())
+    }
+
+    /// Gyro bias (deg/sec) the temperature model predicts at the latest die temperature.
+    fn gyr_temp_bias(&self) -> [f32; 3] {
+        match self.gyr_temp_model {
+            Some(ref model) => model.bias(self.temperature),
+            None => [0.0; 3],
+        }
+    }
+
+    /// Whether the magnetometer heading takes part in the fusion, that is once it is present and
+    /// calibrated.
+    fn mag_fused(&self) -> bool {
+        self.mag_present && self.mag_calibration.is_some()
+    }
+
+    /// Reads the magnetometer when its heading takes part in the fusion.
+    fn measure_mag_if_fused(&mut self) -> Result<(), Bmx055Error<B::Error>> {
+        if self.mag_fused() {
+            self.measure_mag()?;
+        }
+        Ok(())
+    }
+
+    fn compensate_gyr(&mut self) {
+        self.gyr_raw = [self.x_gyr, self.y_gyr, self.z_gyr];
+        let [b_x, b_y, b_z] = self.gyr_temp_bias();
+        self.x_gyr -= self.x_gyr_init + b_x;
+        self.y_gyr -= self.y_gyr_init + b_y;
+        self.z_gyr -= self.z_gyr_init + b_z;
+    }
+
+    /// Takes the current orientation as the zero pose. The reference is kept across `reset`.
+    pub fn tare(&mut self, mode: TareMode) {
+        self.tare.capture(&self.imu_data.get_quaternion(), mode);
+    }
+
+    pub fn clear_tare(&mut self) {
+        self.tare.clear();
+    }
+
+    /// Orientation relative to the tared zero pose.
+    pub fn get_quaternion(&self) -> Quaternion {
+        self.tare.apply(&self.imu_data.get_quaternion())
+    }
+
+    /// Restarts the fusion filter, keeping the tare reference.
+    pub fn reset(&mut self) {
+        self.imu_data.reset();
+        self.stationary.reset();
+    }
+
+    pub fn set_stationary_detector(&mut self, detector: StationaryDetector) {
+        self.stationary = detector;
+    }
+
+    /// Whether the latest update found the device at rest.
+    pub fn is_stationary(&self) -> bool {
+        self.stationary.is_stationary()
+    }
+
+    fn detect_stationary(&mut self) {
+        self.stationary.update(
+            [self.x_acc, self.y_acc, self.z_acc],
+            [self.x_gyr, self.y_gyr, self.z_gyr],
+        );
+    }
+
+    /// Rate (deg/sec) given to the filter. At rest the measured rate is only bias, so the gyro
+    /// offsets take up what the filter's own bias leaves of it and the filter is given a zero
+    /// rate (after its own bias removal) to stop the yaw from drifting. `dt` (sec) is the time
+    /// the sample stands for, so that the offsets follow at the same pace whatever the sampling.
+    fn gyro_input(&mut self, dt: f32) -> [f32; 3] {
+        if !self.stationary.is_stationary() {
+            return [self.x_gyr, self.y_gyr, self.z_gyr];
+        }
+
+        let bias = self.imu_data.get_gyro_bias();
+        let bias = [
+            bias[0] / DEG_TO_RAD,
+            bias[1] / DEG_TO_RAD,
+            bias[2] / DEG_TO_RAD,
+        ];
+        let step = (STATIONARY_BIAS_RATE * dt).min(1.0);
+        self.x_gyr_init += step * (self.x_gyr - bias[0]);
+        self.y_gyr_init += step * (self.y_gyr - bias[1]);
+        self.z_gyr_init += step * (self.z_gyr - bias[2]);
+        bias
+    }
+
+    /// Latest accelerometer reading (m/s^2), calibrated.
+    pub fn get_acc(&self) -> [f32; 3] {
+        [self.x_acc, self.y_acc, self.z_acc]
+    }
+
+    /// Latest gyro reading with the offsets removed (deg/sec).
+    pub fn get_gyr(&self) -> [f32; 3] {
+        [self.x_gyr, self.y_gyr, self.z_gyr]
+    }
+
+    /// Latest gyro reading before any offset is removed (deg/sec), as recorded to fit a
+    /// temperature model.
+    pub fn get_raw_gyr(&self) -> [f32; 3] {
+        self.gyr_raw
+    }
+
+    /// Die temperature (degrees Celsius) read with the latest accelerometer reading.
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+
+    /// Latest compensated magnetometer reading (uT), calibrated when a calibration is set. The
+    /// updates only read the magnetometer once it is calibrated, see `sample_mag` otherwise.
+    pub fn get_mag(&self) -> [f32; 3] {
+        [self.x_mag, self.y_mag, self.z_mag]
+    }
+
+    /// Reads the magnetometer now, e.g. to record the samples `mag_calibrate` needs.
+    pub fn sample_mag(&mut self) -> Result<[f32; 3], Bmx055Error<B::Error>> {
+        self.measure_mag()?;
+        Ok(self.get_mag())
+    }
+
+    /// Gravity-free acceleration (m/s^2) in the sensor frame.
+    pub fn get_linear_acc(&self) -> [f32; 3] {
+        motion::linear_acceleration(
+            &self.imu_data.get_quaternion(),
+            [self.x_acc, self.y_acc, self.z_acc],
+            GRAVITY,
+        )
+    }
 
-            self.y_acc = ((data[3] as f32 * 256.0) + (data[2] & 0xF0) as f32) / 16.0;
-            if self.y_acc > 2047.0 {
-                self.y_acc -= 4096.0
+    /// Gravity-free acceleration (m/s^2) in the earth frame.
+    pub fn get_earth_acc(&self) -> [f32; 3] {
+        motion::earth_linear_acceleration(
+            &self.imu_data.get_quaternion(),
+            [self.x_acc, self.y_acc, self.z_acc],
+            GRAVITY,
+        )
+    }
+
+    /// Reads and fuses one sample, the filter integrating over its own sample period. Meant to
+    /// be called at the gyro's output data rate.
+    pub fn update(&mut self) -> Result<(), Bmx055Error<B::Error>> {
+        self.measure_acc()?;
+        self.measure_temp()?;
+        self.measure_gyr()?;
+        self.measure_mag_if_fused()?;
+        self.compensate_gyr();
+        self.detect_stationary();
+        let [g_x, g_y, g_z] = self.gyro_input(1.0 / self.gyr_settings.odr.hz() as f32);
+        if self.mag_fused() {
+            self.imu_data.update_marg(
+                self.x_acc, self.y_acc, self.z_acc, g_x, g_y, g_z, self.x_mag, self.y_mag,
+                self.z_mag,
+            );
+        } else {
+            self.imu_data
+                .update_imu(self.x_acc, self.y_acc, self.z_acc, g_x, g_y, g_z);
+        }
+        Ok(())
+    }
+
+    pub fn update_dt(&mut self, dt: f32) -> Result<(), Bmx055Error<B::Error>> {
+        self.measure_acc()?;
+        self.measure_temp()?;
+        self.measure_gyr()?;
+        self.measure_mag_if_fused()?;
+        self.compensate_gyr();
+        self.detect_stationary();
+        let [g_x, g_y, g_z] = self.gyro_input(dt);
+        if self.mag_fused() {
+            self.imu_data.update_marg_dt(
+                self.x_acc, self.y_acc, self.z_acc, g_x, g_y, g_z, self.x_mag, self.y_mag,
+                self.z_mag, dt,
+            );
+        } else {
+            self.imu_data
+                .update_imu_dt(self.x_acc, self.y_acc, self.z_acc, g_x, g_y, g_z, dt);
+        }
+        Ok(())
+    }
+
+    /// High rate half of multi-rate fusion: reads the gyro and propagates the orientation.
+    pub fn update_gyro_dt(&mut self, dt: f32) -> Result<(), Bmx055Error<B::Error>> {
+        self.measure_gyr()?;
+        self.propagate(dt);
+        Ok(())
+    }
+
+    fn propagate(&mut self, dt: f32) {
+        self.compensate_gyr();
+        let [g_x, g_y, g_z] = self.gyro_input(dt);
+        self.imu_data.update_gyro_dt(g_x, g_y, g_z, dt);
+    }
+
+    /// Low rate half of multi-rate fusion: reads the accelerometer and corrects the orientation,
+    /// `dt` (sec) being the time since the previous correction. Rest is detected at this rate,
+    /// against the latest gyro sample.
+    pub fn correct_dt(&mut self, dt: f32) -> Result<(), Bmx055Error<B::Error>> {
+        self.measure_acc()?;
+        self.measure_temp()?;
+        self.measure_mag_if_fused()?;
+        self.correct(dt);
+        Ok(())
+    }
+
+    fn correct(&mut self, dt: f32) {
+        self.detect_stationary();
+        if self.mag_fused() {
+            self.imu_data.correct_marg_dt(
+                self.x_acc, self.y_acc, self.z_acc, self.x_mag, self.y_mag, self.z_mag, dt,
+            );
+        } else {
+            self.imu_data
+                .correct_imu_dt(self.x_acc, self.y_acc, self.z_acc, dt);
+        }
+    }
+
+    /// Reads every frame buffered by the gyro and the accelerometer FIFOs, one burst each, and
+    /// fuses them in the order they were sampled: each gyro frame propagates the orientation
+    /// by a gyro sample period and each accelerometer frame corrects it. A calibrated
+    /// magnetometer is read once per drain.
+    pub fn drain_fifo(&mut self) -> Result<FifoReport, Bmx055Error<B::Error>> {
+        let mut status = [0u8; 1];
+        self.read_registers(Chip::Gyr, fifo::REG_STATUS, &mut status)?;
+        let gyr_status = FifoStatus::from_register(status[0]);
+        self.read_registers(Chip::Acc, fifo::REG_STATUS, &mut status)?;
+        let acc_status = FifoStatus::from_register(status[0]);
+
+        let gyr_frames = (gyr_status.frames as usize).min(gyr::FIFO_FRAMES);
+        let mut gyr_data = [0u8; gyr::FIFO_FRAMES * fifo::FRAME_SIZE];
+        if gyr_frames > 0 {
+            let len = gyr_frames * fifo::FRAME_SIZE;
+            self.read_registers(Chip::Gyr, fifo::REG_DATA, &mut gyr_data[..len])?;
+        }
+        let acc_frames = (acc_status.frames as usize).min(acc::FIFO_FRAMES);
+        let mut acc_data = [0u8; acc::FIFO_FRAMES * fifo::FRAME_SIZE];
+        if acc_frames > 0 {
+            let len = acc_frames * fifo::FRAME_SIZE;
+            self.read_registers(Chip::Acc, fifo::REG_DATA, &mut acc_data[..len])?;
+        }
+        self.measure_temp()?;
+        self.measure_mag_if_fused()?;
+
+        // both newest frames were sampled about now, the older ones a period apart before
+        let gyr_dt = 1.0 / self.gyr_settings.odr.hz() as f32;
+        let acc_dt = 1.0 / self.acc_settings.bandwidth.odr();
+        let (mut i, mut j) = (0, 0);
+        while i < gyr_frames || j < acc_frames {
+            let gyr_time = (i as f32 - gyr_frames as f32) * gyr_dt;
+            let acc_time = (j as f32 - acc_frames as f32) * acc_dt;
+            if j >= acc_frames || (i < gyr_frames && gyr_time <= acc_time) {
+                let frame = &gyr_data[i * fifo::FRAME_SIZE..(i + 1) * fifo::FRAME_SIZE];
+                self.decode_gyr(frame);
+                self.propagate(gyr_dt);
+                i += 1;
+            } else {
+                let frame = &acc_data[j * fifo::FRAME_SIZE..(j + 1) * fifo::FRAME_SIZE];
+                self.decode_acc(frame);
+                self.correct(acc_dt);
+                j += 1;
             }
+        }
+
+        // the overrun flags only clear by rewriting the mode, which also empties the FIFOs
+        if gyr_status.overrun {
+            self.write_register(Chip::Gyr, fifo::REG_CONFIG_1, self.fifo.config_1())?;
+        }
+        if acc_status.overrun {
+            self.write_register(Chip::Acc, fifo::REG_CONFIG_1, self.fifo.config_1())?;
+        }
+        let overrun = gyr_status.overrun || acc_status.overrun;
+        if overrun {
+            self.fifo_overruns = self.fifo_overruns.saturating_add(1);
+        }
+        Ok(FifoReport {
+            acc_frames: acc_frames as u8,
+            gyr_frames: gyr_frames as u8,
+            overrun,
+        })
+    }
+}
+
+/// Saves the gyro offsets with the temperature model they are relative to, the magnetometer and
+/// accelerometer calibrations, the tare reference and the filter state.
+impl<B, F: Snapshot> Snapshot for IMU<B, F> {
+    const SIZE: usize = 12
+        + Option::<GyroTempModel>::SIZE
+        + Option::<MagCalibration>::SIZE
+        + AccCalibration::SIZE
+        + Tare::SIZE
+        + F::SIZE;
+
+    fn save(&self, w: &mut Writer) {
+        w.f32(self.x_gyr_init);
+        w.f32(self.y_gyr_init);
+        w.f32(self.z_gyr_init);
+        self.gyr_temp_model.save(w);
+        self.mag_calibration.save(w);
+        self.acc_calibration.save(w);
+        self.tare.save(w);
+        self.imu_data.save(w);
+    }
 
-            self.z_acc = ((data[5] as f32 * 256.0) + (data[4] & 0xF0) as f32) / 16.0;
-            if self.z_acc > 2047.0 {
-                self.z_acc -= 4096.0
+    fn load(&mut self, r: &mut Reader) {
+        self.x_gyr_init = r.f32();
+        self.y_gyr_init = r.f32();
+        self.z_gyr_init = r.f32();
+        self.gyr_temp_model.load(r);
+        self.mag_calibration.load(r);
+        self.acc_calibration.load(r);
+        self.tare.load(r);
+        self.imu_data.load(r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::handler::madgwick::Estimated;
+    use crate::handler::transport::{I2cTransport, ADDR_ACC, ADDR_GYR, ADDR_MAG};
+    use core::cell::RefCell;
+    use embedded_hal::blocking::i2c::{Write, WriteRead};
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Access {
+        Read(u8, u8),
+        Write(u8, u8, u8),
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Nack;
+
+    // register maps of the three cores, as after power on
+    struct Registers {
+        acc: [u8; 0x80],
+        gyr: [u8; 0x80],
+        mag: [u8; 0x80],
+        gyr_fifo: Vec<u8>,
+        fail: bool,
+        absent: Option<u8>,
+        log: Vec<Access>,
+    }
+
+    impl Registers {
+        fn new() -> Self {
+            let mut registers = Registers {
+                acc: [0; 0x80],
+                gyr: [0; 0x80],
+                mag: [0; 0x80],
+                gyr_fifo: Vec::new(),
+                fail: false,
+                absent: None,
+                log: Vec::new(),
+            };
+            registers.acc[acc::REG_CHIP_ID as usize] = acc::CHIP_ID;
+            registers.gyr[gyr::REG_CHIP_ID as usize] = gyr::CHIP_ID;
+            registers.mag[mag::REG_CHIP_ID as usize] = mag::CHIP_ID;
+            registers
+        }
+
+        fn map(&mut self, addr: u8) -> Result<&mut [u8; 0x80], Nack> {
+            if self.absent == Some(addr) {
+                return Err(Nack);
+            }
+            match addr {
+                ADDR_ACC => Ok(&mut self.acc),
+                ADDR_GYR => Ok(&mut self.gyr),
+                ADDR_MAG => Ok(&mut self.mag),
+                _ => Err(Nack),
             }
+        }
+
+        fn set_acc(&mut self, x: i16, y: i16, z: i16) {
+            Self::set_xyz(&mut self.acc, x << 4, y << 4, z << 4);
+        }
+
+        fn set_gyr(&mut self, x: i16, y: i16, z: i16) {
+            Self::set_xyz(&mut self.gyr, x, y, z);
+        }
 
-            self.x_acc *= coefficient;
-            self.y_acc *= coefficient;
-            self.z_acc *= coefficient;
+        fn set_xyz(map: &mut [u8; 0x80], x: i16, y: i16, z: i16) {
+            for (i, v) in [x, y, z].iter().enumerate() {
+                let bytes = v.to_le_bytes();
+                map[0x02 + 2 * i] = bytes[0];
+                map[0x03 + 2 * i] = bytes[1];
+            }
         }
     }
 
-    fn measure_gyr(&mut self) {
-        let addr = [0x02];
-        let mut data = [0u8; 6];
-        let coefficient = 0.003815; // 32767 <-> 2000degree/sec
-        if let Ok(_) = self.dev.write_read(ADDR_GYR, &addr, &mut data) {
-            self.x_gyr = (data[1] as f32 * 256.0) + data[0] as f32;
-            if self.x_gyr > 32767.0 {
-                self.x_gyr -= 65536.0
+    struct MockBus<'a>(&'a RefCell<Registers>);
+
+    impl Write for MockBus<'_> {
+        type Error = Nack;
+
+        fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Nack> {
+            let mut registers = self.0.borrow_mut();
+            if registers.fail {
+                return Err(Nack);
             }
+            registers.map(addr)?[bytes[0] as usize] = bytes[1];
+            registers.log.push(Access::Write(addr, bytes[0], bytes[1]));
+            Ok(())
+        }
+    }
 
-            self.y_gyr = (data[3] as f32 * 256.0) + data[2] as f32;
-            if self.y_gyr > 32767.0 {
-                self.y_gyr -= 65536.0
+    impl WriteRead for MockBus<'_> {
+        type Error = Nack;
+
+        fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Nack> {
+            let mut registers = self.0.borrow_mut();
+            if registers.fail {
+                return Err(Nack);
+            }
+            let reg = bytes[0];
+            registers.log.push(Access::Read(addr, reg));
+            if addr == ADDR_GYR && reg == fifo::REG_DATA {
+                // the data register pops the FIFO
+                let popped: Vec<u8> = registers.gyr_fifo.drain(..buffer.len()).collect();
+                buffer.copy_from_slice(&popped);
+                return Ok(());
             }
+            let map = registers.map(addr)?;
+            buffer.copy_from_slice(&map[reg as usize..reg as usize + buffer.len()]);
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayMs<u32> for NoDelay {
+        fn delay_ms(&mut self, _ms: u32) {}
+    }
+
+    fn imu(registers: &RefCell<Registers>) -> IMU<I2cTransport<MockBus<'_>>, Estimated> {
+        IMU::new(
+            I2cTransport::new(MockBus(registers)),
+            Estimated::new(0.1, 100.0),
+        )
+    }
 
-            self.z_gyr = (data[5] as f32 * 256.0) + data[4] as f32;
-            if self.z_gyr > 32767.0 {
-                self.z_gyr -= 65536.0
+    // filter with a fixed bias, remembering the rate it was last given and whether a
+    // magnetometer sample came with it
+    struct BiasedFilter {
+        bias: [f32; 3],
+        rate: [f32; 3],
+        marg: bool,
+    }
+
+    impl OrientationFilter for BiasedFilter {
+        fn update_imu(&mut self, _a_x: f32, _a_y: f32, _a_z: f32, g_x: f32, g_y: f32, g_z: f32) {
+            self.rate = [g_x, g_y, g_z];
+            self.marg = false;
+        }
+
+        fn update_marg(
+            &mut self,
+            a_x: f32,
+            a_y: f32,
+            a_z: f32,
+            g_x: f32,
+            g_y: f32,
+            g_z: f32,
+            _m_x: f32,
+            _m_y: f32,
+            _m_z: f32,
+        ) {
+            self.update_imu(a_x, a_y, a_z, g_x, g_y, g_z);
+            self.marg = true;
+        }
+
+        fn update_imu_dt(
+            &mut self,
+            a_x: f32,
+            a_y: f32,
+            a_z: f32,
+            g_x: f32,
+            g_y: f32,
+            g_z: f32,
+            _dt: f32,
+        ) {
+            self.update_imu(a_x, a_y, a_z, g_x, g_y, g_z);
+        }
+
+        fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, _dt: f32) {
+            self.rate = [g_x, g_y, g_z];
+        }
+
+        fn correct_imu_dt(&mut self, _a_x: f32, _a_y: f32, _a_z: f32, _dt: f32) {}
+
+        fn get_quaternion(&self) -> Quaternion {
+            Quaternion::default()
+        }
+
+        fn set_quaternion(&mut self, _q: Quaternion) {}
+
+        fn reset(&mut self) {}
+
+        fn get_gyro_bias(&self) -> [f32; 3] {
+            self.bias
+        }
+    }
+
+    #[test]
+    fn rest_shares_bias_with_filter() {
+        let registers = RefCell::new(Registers::new());
+        registers.borrow_mut().set_acc(0, 0, 1024);
+        let resolution = gyr::GyrRange::Dps125.resolution();
+        let offset = 524.0 * resolution; // about 2 deg/sec
+        registers.borrow_mut().set_gyr(524, 0, 0);
+
+        // the filter has already learned a quarter of the offset
+        let filter_bias = offset / 4.0;
+        let mut imu = IMU::new(
+            I2cTransport::new(MockBus(&registers)),
+            BiasedFilter {
+                bias: [filter_bias * DEG_TO_RAD, 0.0, 0.0],
+                rate: [0.0; 3],
+                marg: false,
+            },
+        );
+        imu.set_stationary_detector(StationaryDetector::new(5.0, 0.01));
+        imu.configure(&mut NoDelay, 0).unwrap();
+        for _ in 0..3000 {
+            imu.update().unwrap();
+        }
+        assert!(imu.is_stationary());
+        assert!((imu.x_gyr_init + filter_bias - offset).abs() < 1e-4);
+
+        // once moving, the filter removes its bias from what is left after the offsets
+        registers.borrow_mut().set_gyr(524 + 7864, 0, 0);
+        imu.update().unwrap();
+        let rate = imu.imu_data.rate[0] - filter_bias;
+        assert!((rate - 7864.0 * resolution).abs() < 1e-3, "{}", rate);
+    }
+
+    #[test]
+    fn rest_adapts_offsets_per_second() {
+        // the share of the offset taken up after 0.1 sec at rest, sampling every `dt`
+        let adapted = |dt: f32| {
+            let registers = RefCell::new(Registers::new());
+            registers.borrow_mut().set_acc(0, 0, 1024);
+            registers.borrow_mut().set_gyr(524, 0, 0);
+            let mut imu = IMU::new(
+                I2cTransport::new(MockBus(&registers)),
+                BiasedFilter {
+                    bias: [0.0; 3],
+                    rate: [0.0; 3],
+                    marg: false,
+                },
+            );
+            imu.set_stationary_detector(StationaryDetector::new(5.0, 0.01));
+            imu.configure(&mut NoDelay, 0).unwrap();
+            // the detector needs a full window before it reports rest
+            for _ in 0..crate::handler::stationary::WINDOW - 1 {
+                imu.update_dt(dt).unwrap();
+            }
+            assert!(!imu.is_stationary());
+            for _ in 0..(0.1 / dt).round() as u32 {
+                imu.update_dt(dt).unwrap();
             }
+            imu.x_gyr_init / (524.0 * gyr::GyrRange::Dps125.resolution())
+        };
+        let fast = adapted(0.001);
+        let slow = adapted(0.01);
+        assert!(fast > 0.5 && fast < 0.8, "{}", fast);
+        assert!((fast - slow).abs() < 0.05, "{} != {}", fast, slow);
+    }
+
+    #[test]
+    fn mag_is_read_only_when_fused() {
+        let registers = RefCell::new(Registers::new());
+        let mut imu = IMU::new(
+            I2cTransport::new(MockBus(&registers)),
+            BiasedFilter {
+                bias: [0.0; 3],
+                rate: [0.0; 3],
+                marg: false,
+            },
+        );
+        imu.configure(&mut NoDelay, 0).unwrap();
+        let mag_reads = || {
+            registers
+                .borrow()
+                .log
+                .iter()
+                .filter(|a| **a == Access::Read(ADDR_MAG, mag::REG_DATA))
+                .count()
+        };
+
+        imu.update().unwrap();
+        imu.correct_dt(0.01).unwrap();
+        assert_eq!(mag_reads(), 0);
+        assert!(!imu.imu_data.marg);
+
+        imu.set_mag_calibration(MagCalibration::default());
+        imu.update().unwrap();
+        assert_eq!(mag_reads(), 1);
+        assert!(imu.imu_data.marg);
+    }
+
+    #[test]
+    fn runs_without_magnetometer() {
+        let registers = RefCell::new(Registers::new());
+        registers.borrow_mut().absent = Some(ADDR_MAG);
+        let mut imu = IMU::new(
+            I2cTransport::new(MockBus(&registers)),
+            BiasedFilter {
+                bias: [0.0; 3],
+                rate: [0.0; 3],
+                marg: false,
+            },
+        );
+        imu.set_mag_calibration(MagCalibration::default());
+        imu.initialize(&mut NoDelay, 0, 4).unwrap();
+        assert!(!imu.has_mag());
+
+        // the calibration alone does not bring the absent magnetometer into the fusion
+        imu.update().unwrap();
+        imu.correct_dt(0.01).unwrap();
+        assert!(!imu.imu_data.marg);
+        assert!(matches!(imu.sample_mag(), Err(Bmx055Error::Bus(Nack))));
+    }
 
-            self.x_gyr *= coefficient;
-            self.y_gyr *= coefficient;
-            self.z_gyr *= coefficient;
+    #[test]
+    fn snapshot_keeps_offsets_with_their_model() {
+        let registers = RefCell::new(Registers::new());
+        let mut saved = imu(&registers);
+        saved.set_gyr_temp_model(GyroTempModel::new(25.0, [1.0, -2.0, 0.5], [0.1, 0.0, -0.2]));
+        saved.set_mag_calibration(MagCalibration::new([3.0, 0.0, -1.0], [[2.0, 0.0, 0.0]; 3]));
+        saved.x_gyr_init = 0.25;
+        let mut blob = [0_u8;
+            IMU::<I2cTransport<MockBus<'_>>, Estimated>::SIZE + crate::handler::snapshot::OVERHEAD];
+        crate::handler::snapshot::save(&saved, &mut blob).unwrap();
+
+        let mut restored = imu(&registers);
+        crate::handler::snapshot::restore(&mut restored, &blob).unwrap();
+        assert_eq!(restored.gyr_temp_model, saved.gyr_temp_model);
+        assert_eq!(restored.mag_calibration, saved.mag_calibration);
+        assert_eq!(restored.x_gyr_init, 0.25);
+    }
+
+    #[test]
+    fn init_checks_ids_then_writes_settings() {
+        let registers = RefCell::new(Registers::new());
+        registers.borrow_mut().set_gyr(10, -20, 30);
+        imu(&registers).initialize(&mut NoDelay, 0, 4).unwrap();
+
+        let r = registers.borrow();
+        assert_eq!(r.log[0], Access::Read(ADDR_ACC, acc::REG_CHIP_ID));
+        assert_eq!(r.log[1], Access::Read(ADDR_GYR, gyr::REG_CHIP_ID));
+        assert_eq!(r.log[2], Access::Write(ADDR_ACC, acc::REG_RANGE, 0x03));
+        let power = r
+            .log
+            .iter()
+            .position(|a| *a == Access::Write(ADDR_MAG, mag::REG_POWER, 0x01))
+            .unwrap();
+        assert_eq!(r.log[power + 1], Access::Read(ADDR_MAG, mag::REG_CHIP_ID));
+
+        assert_eq!(&r.acc[0x0F..0x12], &[0x03, 0x08, 0x00]);
+        assert_eq!(&r.gyr[0x0F..0x12], &[0x04, 0x02, 0x00]);
+        assert_eq!(r.acc[fifo::REG_CONFIG_1 as usize], 0x00);
+        assert_eq!(r.mag[mag::REG_INT_AXES as usize], mag::INT_AXES);
+        assert_eq!(r.mag[mag::REG_REP_XY as usize], 4);
+        assert_eq!(r.mag[mag::REG_REP_Z as usize], 14);
+    }
+
+    #[test]
+    fn init_removes_gyro_offsets() {
+        let registers = RefCell::new(Registers::new());
+        registers.borrow_mut().set_acc(0, 0, 1024);
+        registers.borrow_mut().set_gyr(100, -200, 300);
+        let mut imu = imu(&registers);
+        imu.initialize(&mut NoDelay, 0, 4).unwrap();
+        imu.update().unwrap();
+        for rate in imu.get_gyr().iter() {
+            assert!(rate.abs() < 1e-6);
         }
     }
 
-    fn compensate_gyr(&mut self) {
-        self.x_gyr -= self.x_gyr_init;
-        self.y_gyr -= self.y_gyr_init;
-        self.z_gyr -= self.z_gyr_init;
+    #[test]
+    fn gyro_bias_follows_temperature() {
+        let registers = RefCell::new(Registers::new());
+        registers.borrow_mut().set_acc(0, 0, 1024);
+        registers.borrow_mut().set_gyr(100, 0, 0);
+        let mut imu = imu(&registers);
+        // 0.1 deg/sec per degree on x, with the bias at 23 degrees left to the offsets
+        imu.set_gyr_temp_model(GyroTempModel::new(23.0, [0.0; 3], [0.1, 0.0, 0.0]));
+        imu.initialize(&mut NoDelay, 0, 4).unwrap();
+        assert_eq!(imu.get_temperature(), 23.0);
+
+        // warming by 10 degrees adds 1 deg/sec, about 262 counts
+        let resolution = gyr::GyrRange::Dps125.resolution();
+        let counts = 100 + (1.0 / resolution) as i16;
+        registers.borrow_mut().acc[acc::REG_TEMP as usize] = 20;
+        registers.borrow_mut().set_gyr(counts, 0, 0);
+        imu.update().unwrap();
+        assert_eq!(imu.get_temperature(), 33.0);
+        assert!((imu.get_raw_gyr()[0] - counts as f32 * resolution).abs() < 1e-6);
+        assert!(imu.get_gyr()[0].abs() < resolution);
     }
 
-    pub fn update(&mut self) {
-        self.measure_acc();
-        self.measure_gyr();
-        self.compensate_gyr();
-        self.imu_data.update_imu(
-            self.x_acc, self.y_acc, self.z_acc, self.x_gyr, self.y_gyr, self.z_gyr,
+    #[test]
+    fn init_takes_heading_from_calibrated_mag() {
+        let registers = RefCell::new(Registers::new());
+        {
+            let mut r = registers.borrow_mut();
+            r.set_acc(0, 0, 1024);
+            // 100 counts along y, with a hall resistance so that the untrimmed x and y scale
+            let base = mag::REG_DATA as usize;
+            r.mag[base + 2] = 4 << 3;
+            r.mag[base + 3] = 3;
+            r.mag[base + 7] = 1;
+        }
+        let mut imu = imu(&registers);
+        imu.initialize(&mut NoDelay, 0, 4).unwrap();
+        assert_eq!(
+            imu.get_quaternion(),
+            Quaternion::from_gravity([0.0, 0.0, 1.0])
+        );
+
+        imu.set_mag_calibration(MagCalibration::default());
+        imu.initialize(&mut NoDelay, 0, 4).unwrap();
+        let q = imu.get_quaternion();
+        let expected = Quaternion::from_axis_angle([0.0, 0.0, 1.0], -core::f32::consts::FRAC_PI_2);
+        for (a, b) in [q.w, q.x, q.y, q.z]
+            .iter()
+            .zip([expected.w, expected.x, expected.y, expected.z].iter())
+        {
+            assert!((a - b).abs() < 1e-5, "{:?} != {:?}", q, expected);
+        }
+    }
+
+    #[test]
+    fn converts_raw_counts_with_sign() {
+        let registers = RefCell::new(Registers::new());
+        let mut imu = imu(&registers);
+        imu.configure(&mut NoDelay, 0).unwrap();
+        registers.borrow_mut().set_acc(-1024, 0, 2047);
+        // the low nibble of the 12 bit accelerometer LSB is not part of the value
+        registers.borrow_mut().acc[0x04] = 0x0F;
+        registers.borrow_mut().set_gyr(16384, -1, i16::MIN);
+        imu.update().unwrap();
+
+        let [x, y, z] = imu.get_acc();
+        assert!((x + GRAVITY).abs() < 1e-4);
+        assert_eq!(y, 0.0);
+        assert!((z - GRAVITY * 2047.0 / 1024.0).abs() < 1e-4);
+
+        let [x, y, z] = imu.get_gyr();
+        assert!((x - 62.5).abs() < 1e-4);
+        assert!((y + 125.0 / 32768.0).abs() < 1e-7);
+        assert!((z + 125.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn scale_follows_range() {
+        let registers = RefCell::new(Registers::new());
+        let mut imu = imu(&registers);
+        imu.set_acc_settings(AccSettings {
+            range: acc::AccRange::G8,
+            ..AccSettings::default()
+        });
+        imu.set_gyr_settings(GyrSettings {
+            range: gyr::GyrRange::Dps2000,
+            ..GyrSettings::default()
+        });
+        imu.configure(&mut NoDelay, 0).unwrap();
+        assert_eq!(registers.borrow().acc[acc::REG_RANGE as usize], 0x08);
+        assert_eq!(registers.borrow().gyr[gyr::REG_RANGE as usize], 0x00);
+
+        registers.borrow_mut().set_acc(256, 0, 0);
+        registers.borrow_mut().set_gyr(16384, 0, 0);
+        imu.update().unwrap();
+        assert!((imu.get_acc()[0] - GRAVITY).abs() < 1e-4);
+        assert!((imu.get_gyr()[0] - 1000.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reports_wrong_chip_and_absent_sensor() {
+        let registers = RefCell::new(Registers::new());
+        registers.borrow_mut().gyr[gyr::REG_CHIP_ID as usize] = 0x00;
+        let result = imu(&registers).configure(&mut NoDelay, 0);
+        assert!(matches!(
+            result,
+            Err(Bmx055Error::WrongChipId {
+                chip: Chip::Gyr,
+                found: 0x00
+            })
+        ));
+
+        registers.borrow_mut().gyr[gyr::REG_CHIP_ID as usize] = gyr::CHIP_ID;
+        // the magnetometer is optional, a wrong one is left out
+        registers.borrow_mut().mag[mag::REG_CHIP_ID as usize] = 0x00;
+        let mut wrong_mag = imu(&registers);
+        wrong_mag.configure(&mut NoDelay, 0).unwrap();
+        assert!(!wrong_mag.has_mag());
+
+        // a NACK is reported as such once the retries are used up
+        registers.borrow_mut().fail = true;
+        let result = imu(&registers).initialize(&mut NoDelay, 0, 4);
+        assert!(matches!(result, Err(Bmx055Error::Bus(Nack))));
+    }
+
+    #[test]
+    fn failed_read_is_not_stale() {
+        let registers = RefCell::new(Registers::new());
+        let mut imu = imu(&registers);
+        imu.configure(&mut NoDelay, 0).unwrap();
+        registers.borrow_mut().fail = true;
+        assert!(matches!(imu.update(), Err(Bmx055Error::Bus(Nack))));
+    }
+
+    #[test]
+    fn drains_fifo_and_reports_overrun() {
+        let registers = RefCell::new(Registers::new());
+        let mut imu = imu(&registers);
+        imu.set_fifo(FifoConfig {
+            mode: fifo::FifoMode::Stream,
+            watermark: 10,
+        });
+        imu.configure(&mut NoDelay, 0).unwrap();
+        {
+            let mut r = registers.borrow_mut();
+            for x in [100_i16, 200, 300].iter() {
+                r.gyr_fifo.extend_from_slice(&x.to_le_bytes());
+                r.gyr_fifo.extend_from_slice(&[0, 0, 0, 0]);
+            }
+            r.gyr[fifo::REG_STATUS as usize] = 0x83;
+            r.log.clear();
+        }
+
+        let report = imu.drain_fifo().unwrap();
+        assert_eq!(
+            report,
+            FifoReport {
+                acc_frames: 0,
+                gyr_frames: 3,
+                overrun: true
+            }
         );
+        assert_eq!(imu.fifo_overruns(), 1);
+        // frames are fused oldest first
+        assert!((imu.get_gyr()[0] - 300.0 * 125.0 / 32768.0).abs() < 1e-6);
+        // the flag is cleared by rewriting the mode of the overrun FIFO only
+        let r = registers.borrow();
+        assert!(r
+            .log
+            .contains(&Access::Write(ADDR_GYR, fifo::REG_CONFIG_1, 0x80)));
+        assert!(!r
+            .log
+            .iter()
+            .any(|a| matches!(a, Access::Write(ADDR_ACC, _, _))));
     }
 }
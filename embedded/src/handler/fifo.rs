@@ -0,0 +1,108 @@
+//! FIFO layout shared by the BMX055 accelerometer and gyroscope.
+//!
+//! Both cores buffer whole x, y, z frames in the same layout as their data registers, report
+//! the number of buffered frames and an overrun flag in the same status register, and drain
+//! through a single data register that a burst read keeps popping.
+
+pub const REG_STATUS: u8 = 0x0E;
+pub const REG_CONFIG_1: u8 = 0x3E;
+pub const REG_DATA: u8 = 0x3F;
+
+/// Bytes of one x, y, z frame.
+pub const FRAME_SIZE: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FifoMode {
+    /// No buffering, only the latest sample is kept.
+    Bypass,
+    /// Buffers until full, then drops new samples.
+    Fifo,
+    /// Buffers until full, then drops the oldest samples.
+    Stream,
+}
+
+impl FifoMode {
+    fn bits(self) -> u8 {
+        match self {
+            FifoMode::Bypass => 0x00,
+            FifoMode::Fifo => 0x40,
+            FifoMode::Stream => 0x80,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FifoConfig {
+    pub mode: FifoMode,
+    /// Buffered frames that raise the watermark interrupt.
+    pub watermark: u8,
+}
+
+impl FifoConfig {
+    /// Value of the mode register, selecting x, y and z frames. Writing it clears the FIFO.
+    pub fn config_1(&self) -> u8 {
+        self.mode.bits()
+    }
+}
+
+impl Default for FifoConfig {
+    fn default() -> Self {
+        FifoConfig {
+            mode: FifoMode::Bypass,
+            watermark: 0,
+        }
+    }
+}
+
+/// Content of the status register.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FifoStatus {
+    pub frames: u8,
+    /// Frames were lost since the FIFO was last configured.
+    pub overrun: bool,
+}
+
+impl FifoStatus {
+    pub fn from_register(value: u8) -> Self {
+        FifoStatus {
+            frames: value & 0x7F,
+            overrun: value & 0x80 != 0,
+        }
+    }
+}
+
+/// Outcome of one drain of both FIFOs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FifoReport {
+    pub acc_frames: u8,
+    pub gyr_frames: u8,
+    /// Either FIFO overflowed before this drain, so samples are missing.
+    pub overrun: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_register() {
+        assert_eq!(
+            FifoStatus::from_register(0x8A),
+            FifoStatus {
+                frames: 10,
+                overrun: true
+            }
+        );
+        assert!(!FifoStatus::from_register(0x7F).overrun);
+    }
+
+    #[test]
+    fn config_register() {
+        let config = FifoConfig {
+            mode: FifoMode::Stream,
+            watermark: 10,
+        };
+        assert_eq!(config.config_1(), 0x80);
+        assert_eq!(FifoConfig::default().config_1(), 0x00);
+    }
+}
@@ -0,0 +1,194 @@
+//! Register layout and settings of the BMX055 gyroscope (the BMG160 core).
+
+use super::interrupt::{IntPin, IntSource, PUSH_PULL_ACTIVE_HIGH};
+
+pub const CHIP_ID: u8 = 0x0F;
+
+pub const REG_CHIP_ID: u8 = 0x00;
+pub const REG_DATA: u8 = 0x02;
+pub const REG_RANGE: u8 = 0x0F;
+pub const REG_BW: u8 = 0x10;
+pub const REG_LPM1: u8 = 0x11;
+pub const REG_INT_EN_0: u8 = 0x15;
+pub const REG_INT_EN_1: u8 = 0x16;
+pub const REG_INT_MAP_1: u8 = 0x18;
+pub const REG_FIFO_WM_EN: u8 = 0x1E;
+pub const REG_FIFO_CONFIG_0: u8 = 0x3D;
+
+/// Frames the FIFO holds.
+pub const FIFO_FRAMES: usize = 100;
+/// Largest FIFO watermark level.
+pub const FIFO_WATERMARK_MAX: u8 = 0x7F;
+
+/// Full scale of the 16 bit output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GyrRange {
+    Dps125,
+    Dps250,
+    Dps500,
+    Dps1000,
+    Dps2000,
+}
+
+impl GyrRange {
+    fn bits(self) -> u8 {
+        match self {
+            GyrRange::Dps2000 => 0x00,
+            GyrRange::Dps1000 => 0x01,
+            GyrRange::Dps500 => 0x02,
+            GyrRange::Dps250 => 0x03,
+            GyrRange::Dps125 => 0x04,
+        }
+    }
+
+    /// Sensitivity (deg/sec/LSB), the full scale spread over 32768 counts each way.
+    pub fn resolution(self) -> f32 {
+        let full_scale = match self {
+            GyrRange::Dps125 => 125.0,
+            GyrRange::Dps250 => 250.0,
+            GyrRange::Dps500 => 500.0,
+            GyrRange::Dps1000 => 1000.0,
+            GyrRange::Dps2000 => 2000.0,
+        };
+        full_scale / 32768.0
+    }
+}
+
+/// Output data rate and filter bandwidth (Hz).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GyrOdr {
+    Odr2000Bw523,
+    Odr2000Bw230,
+    Odr1000Bw116,
+    Odr400Bw47,
+    Odr200Bw23,
+    Odr100Bw12,
+    Odr200Bw64,
+    Odr100Bw32,
+}
+
+impl GyrOdr {
+    fn bits(self) -> u8 {
+        match self {
+            GyrOdr::Odr2000Bw523 => 0x00,
+            GyrOdr::Odr2000Bw230 => 0x01,
+            GyrOdr::Odr1000Bw116 => 0x02,
+            GyrOdr::Odr400Bw47 => 0x03,
+            GyrOdr::Odr200Bw23 => 0x04,
+            GyrOdr::Odr100Bw12 => 0x05,
+            GyrOdr::Odr200Bw64 => 0x06,
+            GyrOdr::Odr100Bw32 => 0x07,
+        }
+    }
+
+    pub const fn hz(self) -> u32 {
+        match self {
+            GyrOdr::Odr2000Bw523 | GyrOdr::Odr2000Bw230 => 2000,
+            GyrOdr::Odr1000Bw116 => 1000,
+            GyrOdr::Odr400Bw47 => 400,
+            GyrOdr::Odr200Bw23 | GyrOdr::Odr200Bw64 => 200,
+            GyrOdr::Odr100Bw12 | GyrOdr::Odr100Bw32 => 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GyrPower {
+    Normal,
+    Suspend,
+    DeepSuspend,
+}
+
+impl GyrPower {
+    fn bits(self) -> u8 {
+        match self {
+            GyrPower::Normal => 0x00,
+            GyrPower::DeepSuspend => 0x20,
+            GyrPower::Suspend => 0x80,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GyrSettings {
+    pub range: GyrRange,
+    pub odr: GyrOdr,
+    pub power: GyrPower,
+}
+
+impl GyrSettings {
+    /// Register and value pairs applying these settings.
+    pub fn registers(&self) -> [(u8, u8); 3] {
+        [
+            (REG_RANGE, self.range.bits()),
+            (REG_BW, self.odr.bits()),
+            (REG_LPM1, self.power.bits()),
+        ]
+    }
+}
+
+impl Default for GyrSettings {
+    fn default() -> Self {
+        GyrSettings {
+            range: GyrRange::Dps125,
+            odr: GyrOdr::Odr1000Bw116,
+            power: GyrPower::Normal,
+        }
+    }
+}
+
+/// Register and value pairs raising `source` on `pin` (INT3 or INT4), or disabling the
+/// interrupts.
+pub fn interrupt_registers(interrupt: Option<(IntSource, IntPin)>) -> [(u8, u8); 4] {
+    let (enable, map, watermark) = match interrupt {
+        None => (0x00, 0x00, 0x00),
+        Some((IntSource::DataReady, IntPin::Int1)) => (0x80, 0x01, 0x00),
+        Some((IntSource::DataReady, IntPin::Int2)) => (0x80, 0x80, 0x00),
+        Some((IntSource::FifoWatermark, IntPin::Int1)) => (0x40, 0x04, 0x80),
+        Some((IntSource::FifoWatermark, IntPin::Int2)) => (0x40, 0x20, 0x80),
+    };
+    [
+        (REG_INT_EN_1, PUSH_PULL_ACTIVE_HIGH),
+        (REG_INT_MAP_1, map),
+        (REG_FIFO_WM_EN, watermark),
+        (REG_INT_EN_0, enable),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_registers() {
+        let settings = GyrSettings {
+            range: GyrRange::Dps2000,
+            odr: GyrOdr::Odr400Bw47,
+            power: GyrPower::Suspend,
+        };
+        assert_eq!(
+            settings.registers(),
+            [(REG_RANGE, 0x00), (REG_BW, 0x03), (REG_LPM1, 0x80)]
+        );
+    }
+
+    #[test]
+    fn fifo_watermark_on_int3() {
+        let regs = interrupt_registers(Some((IntSource::FifoWatermark, IntPin::Int1)));
+        assert_eq!(
+            regs,
+            [
+                (REG_INT_EN_1, 0x05),
+                (REG_INT_MAP_1, 0x04),
+                (REG_FIFO_WM_EN, 0x80),
+                (REG_INT_EN_0, 0x40)
+            ]
+        );
+    }
+
+    #[test]
+    fn resolution_matches_full_scale() {
+        assert!((GyrRange::Dps125.resolution() - 0.003_815).abs() < 1e-6);
+        assert!((GyrRange::Dps2000.resolution() * 32768.0 - 2000.0).abs() < 1e-3);
+    }
+}
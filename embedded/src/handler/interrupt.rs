@@ -0,0 +1,30 @@
+//! Routing of the BMX055 accelerometer and gyroscope interrupts to their INT pins.
+//!
+//! Each core has two pins: INT1 and INT2 on the accelerometer, INT3 and INT4 (its INT1 and
+//! INT2) on the gyroscope. Pins are driven push-pull and active high.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntPin {
+    Int1,
+    Int2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntSource {
+    /// A new sample is in the data registers.
+    DataReady,
+    /// The FIFO holds as many frames as its watermark.
+    FifoWatermark,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InterruptConfig {
+    pub source: IntSource,
+    /// Pin of the accelerometer raising the interrupt, if any.
+    pub acc: Option<IntPin>,
+    /// Pin of the gyroscope raising the interrupt, if any.
+    pub gyr: Option<IntPin>,
+}
+
+/// Push-pull active high on both pins, in the shared layout of the output control registers.
+pub const PUSH_PULL_ACTIVE_HIGH: u8 = 0x05;
@@ -0,0 +1,297 @@
+//! Register layout and compensation of the BMX055 magnetometer (the BMM150 core).
+//!
+//! The compensation follows the floating point formulas of the Bosch BMM150 API, which turn raw
+//! counts into microtesla using the factory trim registers and the hall resistance.
+
+pub const CHIP_ID: u8 = 0x32;
+
+pub const REG_CHIP_ID: u8 = 0x40;
+pub const REG_DATA: u8 = 0x42;
+pub const REG_POWER: u8 = 0x4B;
+pub const REG_OPMODE: u8 = 0x4C;
+pub const REG_INT_AXES: u8 = 0x4E;
+pub const REG_REP_XY: u8 = 0x51;
+pub const REG_REP_Z: u8 = 0x52;
+
+pub const REG_TRIM_X1Y1: u8 = 0x5D;
+pub const REG_TRIM_Z4X2Y2: u8 = 0x62;
+pub const REG_TRIM_Z2Z1XYZ1Z3XY2XY1: u8 = 0x68;
+
+/// All axes enabled, data ready pin enabled and active high.
+pub const INT_AXES: u8 = 0x84;
+
+/// Milliseconds from power control on to the sleep state.
+pub const POWER_UP_MS: u32 = 3;
+
+const OVERFLOW_XY: i16 = -4096;
+const OVERFLOW_Z: i16 = -16384;
+
+/// Output data rate in normal mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MagOdr {
+    Hz2,
+    Hz6,
+    Hz8,
+    Hz10,
+    Hz15,
+    Hz20,
+    Hz25,
+    Hz30,
+}
+
+impl MagOdr {
+    fn bits(self) -> u8 {
+        match self {
+            MagOdr::Hz10 => 0b000,
+            MagOdr::Hz2 => 0b001,
+            MagOdr::Hz6 => 0b010,
+            MagOdr::Hz8 => 0b011,
+            MagOdr::Hz15 => 0b100,
+            MagOdr::Hz20 => 0b101,
+            MagOdr::Hz25 => 0b110,
+            MagOdr::Hz30 => 0b111,
+        }
+    }
+}
+
+/// Measurement settings: more repetitions lower the noise and raise the current.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MagSettings {
+    /// Repetitions on x and y, odd from 1 to 511.
+    pub rep_xy: u16,
+    /// Repetitions on z, from 1 to 256.
+    pub rep_z: u16,
+    pub odr: MagOdr,
+}
+
+impl MagSettings {
+    /// Bosch's low power preset.
+    pub const LOW_POWER: Self = Self {
+        rep_xy: 3,
+        rep_z: 3,
+        odr: MagOdr::Hz10,
+    };
+    /// Bosch's regular preset.
+    pub const REGULAR: Self = Self {
+        rep_xy: 9,
+        rep_z: 15,
+        odr: MagOdr::Hz10,
+    };
+    /// Bosch's enhanced regular preset.
+    pub const ENHANCED: Self = Self {
+        rep_xy: 15,
+        rep_z: 27,
+        odr: MagOdr::Hz10,
+    };
+    /// Bosch's high accuracy preset.
+    pub const HIGH_ACCURACY: Self = Self {
+        rep_xy: 47,
+        rep_z: 83,
+        odr: MagOdr::Hz20,
+    };
+
+    /// Register and value pairs selecting normal mode with these settings.
+    pub fn registers(&self) -> [(u8, u8); 3] {
+        [
+            (REG_OPMODE, self.odr.bits() << 3),
+            (REG_REP_XY, ((self.rep_xy.max(1) - 1) / 2) as u8),
+            (REG_REP_Z, (self.rep_z.max(1) - 1) as u8),
+        ]
+    }
+}
+
+impl Default for MagSettings {
+    fn default() -> Self {
+        Self::REGULAR
+    }
+}
+
+/// Raw counts of one measurement.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MagRaw {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub rhall: u16,
+}
+
+impl MagRaw {
+    /// Unpacks the 8 data registers from 0x42: 13 bit x and y, 15 bit z and 14 bit rhall.
+    pub fn from_registers(data: &[u8; 8]) -> Self {
+        MagRaw {
+            x: ((data[1] as i8 as i16) << 5) | (data[0] >> 3) as i16,
+            y: ((data[3] as i8 as i16) << 5) | (data[2] >> 3) as i16,
+            z: ((data[5] as i8 as i16) << 7) | (data[4] >> 1) as i16,
+            rhall: ((data[7] as u16) << 6) | (data[6] >> 2) as u16,
+        }
+    }
+}
+
+/// Factory trim values of one chip.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MagTrim {
+    pub dig_x1: i8,
+    pub dig_y1: i8,
+    pub dig_x2: i8,
+    pub dig_y2: i8,
+    pub dig_z1: u16,
+    pub dig_z2: i16,
+    pub dig_z3: i16,
+    pub dig_z4: i16,
+    pub dig_xy1: u8,
+    pub dig_xy2: i8,
+    pub dig_xyz1: u16,
+}
+
+impl MagTrim {
+    /// Unpacks the trim registers read from 0x5D (2 bytes), 0x62 (4 bytes) and 0x68 (10 bytes).
+    pub fn from_registers(x1y1: &[u8; 2], z4x2y2: &[u8; 4], rest: &[u8; 10]) -> Self {
+        MagTrim {
+            dig_x1: x1y1[0] as i8,
+            dig_y1: x1y1[1] as i8,
+            dig_z4: i16::from_le_bytes([z4x2y2[0], z4x2y2[1]]),
+            dig_x2: z4x2y2[2] as i8,
+            dig_y2: z4x2y2[3] as i8,
+            dig_z2: i16::from_le_bytes([rest[0], rest[1]]),
+            dig_z1: u16::from_le_bytes([rest[2], rest[3]]),
+            dig_xyz1: u16::from_le_bytes([rest[4], rest[5] & 0x7F]),
+            dig_z3: i16::from_le_bytes([rest[6], rest[7]]),
+            dig_xy2: rest[8] as i8,
+            dig_xy1: rest[9],
+        }
+    }
+
+    /// Compensated field (uT), zero on axes that overflowed.
+    pub fn compensate(&self, raw: &MagRaw) -> [f32; 3] {
+        [
+            self.compensate_xy(raw.x, raw.rhall, self.dig_x1, self.dig_x2),
+            self.compensate_xy(raw.y, raw.rhall, self.dig_y1, self.dig_y2),
+            self.compensate_z(raw.z, raw.rhall),
+        ]
+    }
+
+    fn compensate_xy(&self, data: i16, rhall: u16, dig_1: i8, dig_2: i8) -> f32 {
+        if data == OVERFLOW_XY {
+            return 0.0;
+        }
+        let r = if rhall != 0 { rhall } else { self.dig_xyz1 };
+        if r == 0 {
+            return 0.0;
+        }
+        let x2 = self.dig_xyz1 as f32 * 16384.0 / r as f32 - 16384.0;
+        let x4 = self.dig_xy2 as f32 * (x2 * x2 / 268_435_456.0);
+        let x5 = x4 + x2 * self.dig_xy1 as f32 / 16384.0;
+        let x6 = dig_2 as f32 + 160.0;
+        let x7 = data as f32 * (x5 + 256.0) * x6;
+        (x7 / 8192.0 + dig_1 as f32 * 8.0) / 16.0
+    }
+
+    fn compensate_z(&self, data: i16, rhall: u16) -> f32 {
+        if data == OVERFLOW_Z
+            || self.dig_z1 == 0
+            || self.dig_z2 == 0
+            || self.dig_xyz1 == 0
+            || rhall == 0
+        {
+            return 0.0;
+        }
+        let z0 = data as f32 - self.dig_z4 as f32;
+        let z1 = rhall as f32 - self.dig_xyz1 as f32;
+        let z2 = self.dig_z3 as f32 * z1;
+        let z3 = self.dig_z1 as f32 * rhall as f32 / 32768.0;
+        let z4 = self.dig_z2 as f32 + z3;
+        let z5 = z0 * 131_072.0 - z2;
+        z5 / (z4 * 4.0) / 16.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // typical trim values of a BMX055
+    fn trim() -> MagTrim {
+        MagTrim {
+            dig_x1: 0,
+            dig_y1: 0,
+            dig_x2: 0,
+            dig_y2: 0,
+            dig_z1: 24_000,
+            dig_z2: 700,
+            dig_z3: 0,
+            dig_z4: 0,
+            dig_xy1: 29,
+            dig_xy2: -3,
+            dig_xyz1: 6800,
+        }
+    }
+
+    #[test]
+    fn unpacks_data_registers() {
+        // x = -1 (13 bit), y = 4095, z = -16384 (overflow), rhall = 6800
+        let data = [0xF8, 0xFF, 0xF8, 0x7F, 0x00, 0x80, 0x40, 0x6A];
+        let raw = MagRaw::from_registers(&data);
+        assert_eq!(raw.x, -1);
+        assert_eq!(raw.y, 4095);
+        assert_eq!(raw.z, -16384);
+        assert_eq!(raw.rhall, 6800);
+    }
+
+    #[test]
+    fn unpacks_trim_registers() {
+        let t = MagTrim::from_registers(
+            &[0xFE, 0x02],
+            &[0x34, 0x12, 0xE6, 0x1A],
+            &[0xBC, 0x02, 0xC0, 0x5D, 0x90, 0x9A, 0x00, 0xF0, 0xFD, 0x1D],
+        );
+        assert_eq!((t.dig_x1, t.dig_y1), (-2, 2));
+        assert_eq!(t.dig_z4, 0x1234);
+        assert_eq!((t.dig_x2, t.dig_y2), (-26, 26));
+        assert_eq!(t.dig_z2, 700);
+        assert_eq!(t.dig_z1, 24_000);
+        // the top bit of xyz1 is not part of the value
+        assert_eq!(t.dig_xyz1, 0x1A90);
+        assert_eq!(t.dig_z3, -4096);
+        assert_eq!((t.dig_xy2, t.dig_xy1), (-3, 29));
+    }
+
+    #[test]
+    fn nominal_sensitivity() {
+        // with rhall at its reference the x/y scale is 0.3125 uT/LSB
+        let t = trim();
+        let raw = MagRaw {
+            x: 100,
+            y: -64,
+            z: 1000,
+            rhall: 6800,
+        };
+        let [x, y, z] = t.compensate(&raw);
+        assert!((x - 31.25).abs() < 1e-3);
+        assert!((y + 20.0).abs() < 1e-3);
+        let expected_z = 1000.0 * 131_072.0 / ((700.0 + 24_000.0 * 6800.0 / 32768.0) * 64.0);
+        assert!((z - expected_z).abs() < 1e-3);
+    }
+
+    #[test]
+    fn overflow_reads_zero() {
+        let raw = MagRaw {
+            x: OVERFLOW_XY,
+            y: 10,
+            z: OVERFLOW_Z,
+            rhall: 6800,
+        };
+        let [x, y, z] = trim().compensate(&raw);
+        assert_eq!(x, 0.0);
+        assert!(y != 0.0);
+        assert_eq!(z, 0.0);
+    }
+
+    #[test]
+    fn settings_registers() {
+        let regs = MagSettings::HIGH_ACCURACY.registers();
+        assert_eq!(
+            regs,
+            [(REG_OPMODE, 0x28), (REG_REP_XY, 23), (REG_REP_Z, 82)]
+        );
+    }
+}
@@ -3,7 +3,7 @@ use stm32f4xx_hal as hal;
 use hal::prelude::_embedded_hal_serial_Write;
 use hal::{block, serial::Tx};
 
-use super::madgwick;
+use super::quaternion::Quaternion;
 
 pub fn transmit_base<T>(tx: &mut Tx<T>, data: &[u8])
 where
@@ -26,14 +26,31 @@ where
     transmit_base(tx, &bytes);
 }
 
-pub fn transmit_quaternion<T>(tx: &mut Tx<T>, data: madgwick::Estimated, header: &[u8])
+pub fn transmit_vector<T>(tx: &mut Tx<T>, data: [f32; 3], header: &[u8])
+where
+    Tx<T>: _embedded_hal_serial_Write<u8>,
+    <Tx<T> as _embedded_hal_serial_Write<u8>>::Error: core::fmt::Debug,
+{
+    let mut bytes = [0_u8; 12];
+    for i in 0..3 {
+        let tmp = data[i].to_le_bytes();
+        for j in 0..4 {
+            bytes[i * 4 + j] = tmp[j];
+        }
+    }
+
+    transmit_base(tx, header);
+    transmit_base(tx, &bytes);
+}
+
+pub fn transmit_quaternion<T>(tx: &mut Tx<T>, data: Quaternion, header: &[u8])
 where
     Tx<T>: _embedded_hal_serial_Write<u8>,
     <Tx<T> as _embedded_hal_serial_Write<u8>>::Error: core::fmt::Debug,
 {
     let mut bytes = [0_u8; 16];
-    for i in 0..4 {
-        let tmp = data.get_q(i as i32).unwrap().to_le_bytes();
+    for (i, q) in data.to_array().iter().enumerate() {
+        let tmp = q.to_le_bytes();
         for j in 0..4 {
             bytes[i * 4 + j] = tmp[j];
         }
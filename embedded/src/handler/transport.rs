@@ -0,0 +1,262 @@
+//! Register access to the three cores of the BMX055 over I2C or 4-wire SPI.
+//!
+//! The driver only reads and writes registers of a core, so the bus is hidden behind
+//! `Transport`. Over I2C each core answers its own address; over SPI each core has its own
+//! chip select and the top bit of the register address selects a read.
+
+use embedded_hal::blocking::{i2c, spi};
+use embedded_hal::digital::v2::OutputPin;
+
+pub const ADDR_ACC: u8 = 0x19;
+pub const ADDR_GYR: u8 = 0x69;
+pub const ADDR_MAG: u8 = 0x13;
+
+const SPI_READ: u8 = 0x80;
+
+/// One of the cores of the BMX055.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip {
+    Acc,
+    Gyr,
+    Mag,
+}
+
+pub trait Transport {
+    type Error;
+
+    fn write_register(&mut self, chip: Chip, reg: u8, value: u8) -> Result<(), Self::Error>;
+
+    /// Reads `data.len()` bytes from `reg` on. The FIFO data register keeps its address, so a
+    /// long read of it drains several frames.
+    fn read_registers(&mut self, chip: Chip, reg: u8, data: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// The cores on an I2C bus, with SDO pulled high (the default of most boards).
+pub struct I2cTransport<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C> I2cTransport<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        I2cTransport { i2c }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    fn address(chip: Chip) -> u8 {
+        match chip {
+            Chip::Acc => ADDR_ACC,
+            Chip::Gyr => ADDR_GYR,
+            Chip::Mag => ADDR_MAG,
+        }
+    }
+}
+
+impl<I2C, E> Transport for I2cTransport<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    type Error = E;
+
+    fn write_register(&mut self, chip: Chip, reg: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(Self::address(chip), &[reg, value])
+    }
+
+    fn read_registers(&mut self, chip: Chip, reg: u8, data: &mut [u8]) -> Result<(), E> {
+        self.i2c.write_read(Self::address(chip), &[reg], data)
+    }
+}
+
+#[derive(Debug)]
+pub enum SpiError<E, P> {
+    Spi(E),
+    ChipSelect(P),
+}
+
+/// The cores on a 4-wire SPI bus (mode 0 or 3), each with its own active low chip select.
+pub struct SpiTransport<SPI, CSA, CSG, CSM> {
+    spi: SPI,
+    cs_acc: CSA,
+    cs_gyr: CSG,
+    cs_mag: CSM,
+}
+
+impl<SPI, CSA, CSG, CSM, E, P> SpiTransport<SPI, CSA, CSG, CSM>
+where
+    SPI: spi::Transfer<u8, Error = E> + spi::Write<u8, Error = E>,
+    CSA: OutputPin<Error = P>,
+    CSG: OutputPin<Error = P>,
+    CSM: OutputPin<Error = P>,
+{
+    /// Takes the bus and the chip selects, deselecting all cores.
+    pub fn new(spi: SPI, cs_acc: CSA, cs_gyr: CSG, cs_mag: CSM) -> Result<Self, SpiError<E, P>> {
+        let mut transport = SpiTransport {
+            spi,
+            cs_acc,
+            cs_gyr,
+            cs_mag,
+        };
+        for chip in [Chip::Acc, Chip::Gyr, Chip::Mag].iter() {
+            transport.select(*chip, false)?;
+        }
+        Ok(transport)
+    }
+
+    pub fn release(self) -> (SPI, CSA, CSG, CSM) {
+        (self.spi, self.cs_acc, self.cs_gyr, self.cs_mag)
+    }
+
+    fn select(&mut self, chip: Chip, selected: bool) -> Result<(), SpiError<E, P>> {
+        let result = match (chip, selected) {
+            (Chip::Acc, true) => self.cs_acc.set_low(),
+            (Chip::Acc, false) => self.cs_acc.set_high(),
+            (Chip::Gyr, true) => self.cs_gyr.set_low(),
+            (Chip::Gyr, false) => self.cs_gyr.set_high(),
+            (Chip::Mag, true) => self.cs_mag.set_low(),
+            (Chip::Mag, false) => self.cs_mag.set_high(),
+        };
+        result.map_err(SpiError::ChipSelect)
+    }
+
+    /// Runs `f` with `chip` selected, deselecting it whatever the outcome.
+    fn transaction<F>(&mut self, chip: Chip, f: F) -> Result<(), SpiError<E, P>>
+    where
+        F: FnOnce(&mut SPI) -> Result<(), E>,
+    {
+        self.select(chip, true)?;
+        let result = f(&mut self.spi).map_err(SpiError::Spi);
+        self.select(chip, false)?;
+        result
+    }
+}
+
+impl<SPI, CSA, CSG, CSM, E, P> Transport for SpiTransport<SPI, CSA, CSG, CSM>
+where
+    SPI: spi::Transfer<u8, Error = E> + spi::Write<u8, Error = E>,
+    CSA: OutputPin<Error = P>,
+    CSG: OutputPin<Error = P>,
+    CSM: OutputPin<Error = P>,
+{
+    type Error = SpiError<E, P>;
+
+    fn write_register(&mut self, chip: Chip, reg: u8, value: u8) -> Result<(), Self::Error> {
+        self.transaction(chip, |spi| spi.write(&[reg & !SPI_READ, value]))
+    }
+
+    fn read_registers(&mut self, chip: Chip, reg: u8, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.transaction(chip, |spi| {
+            spi.write(&[reg | SPI_READ])?;
+            for byte in data.iter_mut() {
+                *byte = 0;
+            }
+            spi.transfer(data).map(|_| ())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Select(Chip, bool),
+        Out(Vec<u8>),
+    }
+
+    // records the bus traffic, answering reads with an incrementing count
+    struct MockSpi<'a>(&'a RefCell<Vec<Event>>);
+
+    impl spi::Write<u8> for MockSpi<'_> {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Event::Out(words.to_vec()));
+            Ok(())
+        }
+    }
+
+    impl spi::Transfer<u8> for MockSpi<'_> {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+            self.0.borrow_mut().push(Event::Out(words.to_vec()));
+            for (i, word) in words.iter_mut().enumerate() {
+                *word = i as u8 + 1;
+            }
+            Ok(words)
+        }
+    }
+
+    struct MockPin<'a>(&'a RefCell<Vec<Event>>, Chip);
+
+    impl OutputPin for MockPin<'_> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Event::Select(self.1, true));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(Event::Select(self.1, false));
+            Ok(())
+        }
+    }
+
+    fn transport(
+        events: &RefCell<Vec<Event>>,
+    ) -> SpiTransport<MockSpi<'_>, MockPin<'_>, MockPin<'_>, MockPin<'_>> {
+        let transport = SpiTransport::new(
+            MockSpi(events),
+            MockPin(events, Chip::Acc),
+            MockPin(events, Chip::Gyr),
+            MockPin(events, Chip::Mag),
+        )
+        .unwrap();
+        events.borrow_mut().clear();
+        transport
+    }
+
+    #[test]
+    fn spi_write_selects_one_chip() {
+        let events = RefCell::new(Vec::new());
+        transport(&events)
+            .write_register(Chip::Gyr, 0x0F, 0x04)
+            .unwrap();
+        assert_eq!(
+            *events.borrow(),
+            [
+                Event::Select(Chip::Gyr, true),
+                Event::Out(std::vec![0x0F, 0x04]),
+                Event::Select(Chip::Gyr, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn spi_read_sets_read_bit() {
+        let events = RefCell::new(Vec::new());
+        let mut data = [0xAA; 3];
+        transport(&events)
+            .read_registers(Chip::Mag, 0x42, &mut data)
+            .unwrap();
+        assert_eq!(data, [1, 2, 3]);
+        assert_eq!(
+            *events.borrow(),
+            [
+                Event::Select(Chip::Mag, true),
+                Event::Out(std::vec![0xC2]),
+                Event::Out(std::vec![0, 0, 0]),
+                Event::Select(Chip::Mag, false),
+            ]
+        );
+    }
+}
@@ -3,9 +3,15 @@
 
 extern crate panic_halt;
 
-use core::{cell::RefCell, /* f32::consts::PI, */ ops::DerefMut};
+use core::{
+    cell::{Cell, RefCell},
+    /* f32::consts::PI, */ ops::DerefMut,
+};
 
-use cortex_m::interrupt::Mutex;
+use cortex_m::{
+    interrupt::{CriticalSection, Mutex},
+    peripheral::DWT,
+};
 use cortex_m_rt::entry;
 
 use stm32f4xx_hal as hal;
@@ -13,21 +19,59 @@ use stm32f4xx_hal as hal;
 use hal::{
     adc::{config::AdcConfig, Adc},
     delay::Delay,
-    gpio::gpioa::PA0, // PA6, PA7
-    gpio::gpiob::{PB8, PB9},
-    gpio::{AlternateOD, Analog, AF4},
-    i2c::I2c,
+    dwt::DwtExt,
+    gpio::gpioa::{PA0, PA10, PA5}, // PA6, PA7
+    gpio::gpiob::{PB4, PB5},
+    gpio::{Analog, Edge, ExtiPin, Floating, Input, Output, PullDown, PushPull},
     interrupt,
     prelude::*,
     serial::Serial,
-    stm32::{self, CorePeripherals, Peripherals, I2C1, USART2}, // ADC1, ADC2, ADC3
+    stm32::{self, CorePeripherals, Peripherals, RCC, USART2}, // ADC1, ADC2, ADC3
     timer::Timer,
 };
 
 use embedded::handler;
+use handler::accelerometer::{AccBandwidth, AccPower, AccRange, AccSettings};
+use handler::bmx055::Bmx055Error;
+use handler::fifo::{FifoConfig, FifoMode};
+use handler::gyroscope::{GyrOdr, GyrPower, GyrRange, GyrSettings};
+use handler::interrupt::{IntPin, IntSource, InterruptConfig};
+use handler::snapshot::{self, Snapshot};
+use handler::transport::Transport;
+
+#[cfg(feature = "spi")]
+use hal::{
+    gpio::gpiob::{PB0, PB1, PB12, PB13, PB14, PB15},
+    gpio::{Alternate, AF5},
+    spi::Spi,
+    stm32::SPI2,
+};
+#[cfg(not(feature = "spi"))]
+use hal::{
+    gpio::gpiob::{PB8, PB9},
+    gpio::{AlternateOD, AF4},
+    i2c::I2c,
+    stm32::I2C1,
+};
 
-type I2cBus = I2C1;
-type I2cPin = (PB8<AlternateOD<AF4>>, PB9<AlternateOD<AF4>>);
+#[cfg(not(feature = "spi"))]
+type ImuBus =
+    handler::transport::I2cTransport<I2c<I2C1, (PB8<AlternateOD<AF4>>, PB9<AlternateOD<AF4>>)>>;
+// SCK, MISO and MOSI, then the chip selects of the accelerometer, gyro and magnetometer
+#[cfg(feature = "spi")]
+type ImuBus = handler::transport::SpiTransport<
+    Spi<
+        SPI2,
+        (
+            PB13<Alternate<AF5>>,
+            PB14<Alternate<AF5>>,
+            PB15<Alternate<AF5>>,
+        ),
+    >,
+    PB12<Output<PushPull>>,
+    PB1<Output<PushPull>>,
+    PB0<Output<PushPull>>,
+>;
 type USBTx = hal::serial::Tx<USART2>;
 
 // shared items
@@ -35,18 +79,113 @@ static UART_TX: Mutex<RefCell<Option<USBTx>>> = Mutex::new(RefCell::new(None));
 
 static TIMER: Mutex<RefCell<Option<Timer<stm32::TIM2>>>> = Mutex::new(RefCell::new(None));
 
+static OUTPUT_TIMER: Mutex<RefCell<Option<Timer<stm32::TIM3>>>> = Mutex::new(RefCell::new(None));
+
+static LAST_CYCLE: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+static LAST_CORRECTION_CYCLE: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+static GYRO_TICKS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+// whether TIM2 polls the IMU, from the start or after the INT lines failed
+static POLLING: Mutex<Cell<bool>> = Mutex::new(Cell::new(matches!(SAMPLING, Sampling::Timer)));
+
+// failed reads in a row started by each INT line
+static GYRO_INT_FAILURES: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+static ACC_INT_FAILURES: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+// whether the latest read of the IMU failed
+static IMU_FAULT: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+// INT3 of the gyro and INT1 of the accelerometer
+static GYRO_INT: Mutex<RefCell<Option<PB4<Input<Floating>>>>> = Mutex::new(RefCell::new(None));
+
+static ACC_INT: Mutex<RefCell<Option<PB5<Input<Floating>>>>> = Mutex::new(RefCell::new(None));
+
+type Imu = handler::bmx055::IMU<ImuBus, handler::madgwick::Estimated>;
+
 struct Devices {
-    imu: handler::bmx055::IMU<I2cBus, I2cPin>,
+    imu: Imu,
     elbow: handler::potentio::Potentiometer<PA0<Analog>>,
 }
 
 static DEVICES: Mutex<RefCell<Option<Devices>>> = Mutex::new(RefCell::new(None));
 
+/// What triggers the reads of the IMU.
+#[derive(Clone, Copy, PartialEq)]
+enum Sampling {
+    /// TIM2 polls at a fixed rate, unaware of the sensors' own sampling clock.
+    Timer,
+    /// The sensors' INT pins raise EXTI interrupts when data is ready.
+    DataReady,
+}
+
 // const parameters
-const CLOCK: u32 = 100; // Hertz
-const INIT_COUNT_IMU: u32 = 1000;
+const HCLK: u32 = 180_000_000; // Hertz
+#[cfg(feature = "spi")]
+const SPI_FREQ: u32 = 8_000_000; // Hertz, the BMX055 takes up to 10 MHz
+const GYR_SETTINGS: GyrSettings = GyrSettings {
+    range: GyrRange::Dps125,
+    odr: GyrOdr::Odr1000Bw116,
+    power: GyrPower::Normal,
+};
+const GYRO_RATE: u32 = GYR_SETTINGS.odr.hz(); // Hertz, gyro integration at the gyro's ODR
+const CORRECTION_RATE: u32 = 100; // Hertz, accelerometer correction
+const ACC_SETTINGS: AccSettings = AccSettings {
+    range: AccRange::G2,
+    // sampling at least as fast as the corrections, so that no sample is fused twice
+    bandwidth: AccBandwidth::for_odr(CORRECTION_RATE),
+    power: AccPower::Normal,
+};
+const OUTPUT_RATE: u32 = 50; // Hertz, UART frames
+const CORRECTION_DIVIDER: u32 = GYRO_RATE / CORRECTION_RATE;
+const USE_FIFO: bool = true; // buffer the samples in the sensor instead of polling each one
+const FIFO_DRAIN_RATE: u32 = 100; // Hertz
+const FIFO_CONFIG: FifoConfig = FifoConfig {
+    mode: FifoMode::Stream,
+    watermark: (GYRO_RATE / FIFO_DRAIN_RATE) as u8,
+};
+const SAMPLE_RATE: u32 = if USE_FIFO { FIFO_DRAIN_RATE } else { GYRO_RATE }; // Hertz, TIM2
+const SAMPLING: Sampling = Sampling::DataReady;
+const INT_RETRIES: u8 = 3; // failed reads started by an INT line before falling back to TIM2
+const INTERRUPTS: InterruptConfig = if USE_FIFO {
+    // the gyro's FIFO watermark, or each gyro and accelerometer sample when not buffering
+    InterruptConfig {
+        source: IntSource::FifoWatermark,
+        acc: None,
+        gyr: Some(IntPin::Int1),
+    }
+} else {
+    InterruptConfig {
+        source: IntSource::DataReady,
+        acc: Some(IntPin::Int1),
+        gyr: Some(IntPin::Int1),
+    }
+};
+const INIT_COUNT_IMU: u32 = 100;
 const INIT_COUNT_ADC: u32 = 100;
+const GRAVITY: f32 = 9.80665; // m/s^2
+const ACC_REJECTION: f32 = 0.3; // relative deviation from GRAVITY
+const GYRO_BIAS_GAIN: f32 = 0.005; // rad/sec^2
+const STARTUP_GAIN: f32 = 2.0;
+const STARTUP_TIME: f32 = 1.0; // sec
+const TARE_MODE: handler::tare::TareMode = handler::tare::TareMode::Full;
+const SWITCH_POLL_MS: u8 = 10;
+const LED_BLINK_TICKS: u8 = 25; // switch polls per LED toggle
+const FAULT_BLINK_TICKS: u8 = 5; // switch polls per LED toggle while the IMU fails
+const SNAPSHOT_TICKS: u8 = 100; // switch polls per save of the IMU state
+const SNAPSHOT_SIZE: usize = Imu::SIZE + snapshot::OVERHEAD;
+// paste the output of `mag_calibrate` here; without it the heading is left to the gyro
+const MAG_CALIBRATION: Option<handler::calibration::MagCalibration> = None;
+// paste the output of `gyro_temp_fit` here; without it the gyro offsets are fixed at startup
+const GYRO_TEMP_MODEL: Option<handler::calibration::GyroTempModel> = None;
+const ACC_CROSS_AXIS: bool = false; // also solve the cross-axis terms of the accelerometer
+const ACC_CALIBRATION_COUNT: u32 = 200; // samples averaged per position
 const HEADER: [u8; 2] = [0xE0, 0xE0];
+// append the earth frame linear acceleration to each frame, 12 more bytes than the reader and
+// the visualizer expect
+const STREAM_LINEAR_ACC: bool = false;
 
 #[entry]
 fn main() -> ! {
@@ -58,14 +197,17 @@ fn main() -> ! {
         let clock = rcc
             .cfgr
             .use_hse(8.mhz())
-            .hclk(180.mhz())
+            .hclk(HCLK.hz())
             .pclk1(45.mhz())
             .pclk2(90.mhz())
-            .sysclk(180.mhz())
+            .sysclk(HCLK.hz())
             .freeze();
 
         let mut delay = Delay::new(core_peripherals.SYST, clock);
 
+        // cycle counter for measuring the sampling interval
+        let _dwt = core_peripherals.DWT.constrain(core_peripherals.DCB, clock);
+
         // usart
         let gpioa = peripherals.GPIOA.split();
         let gpio_tx = gpioa.pa2.into_alternate_af7();
@@ -89,76 +231,407 @@ fn main() -> ! {
 
         // i2c
         let gpiob = peripherals.GPIOB.split();
-        let i2c_scl = gpiob.pb8.into_alternate_af4_open_drain();
-        let i2c_sda = gpiob.pb9.into_alternate_af4_open_drain();
+        #[cfg(not(feature = "spi"))]
+        let imu_bus = {
+            let i2c_scl = gpiob.pb8.into_alternate_af4_open_drain();
+            let i2c_sda = gpiob.pb9.into_alternate_af4_open_drain();
+
+            let i2c = I2c::i2c1(
+                peripherals.I2C1,
+                (i2c_scl, i2c_sda),
+                hal::time::KiloHertz(400),
+                clock,
+            );
+            handler::transport::I2cTransport::new(i2c)
+        };
 
-        let i2c = I2c::i2c1(
-            peripherals.I2C1,
-            (i2c_scl, i2c_sda),
-            hal::time::KiloHertz(100),
-            clock,
-        );
+        // spi, with the sensor's PS pin tied low
+        #[cfg(feature = "spi")]
+        let imu_bus = {
+            let spi_sck = gpiob.pb13.into_alternate_af5();
+            let spi_miso = gpiob.pb14.into_alternate_af5();
+            let spi_mosi = gpiob.pb15.into_alternate_af5();
+
+            let spi = Spi::spi2(
+                peripherals.SPI2,
+                (spi_sck, spi_miso, spi_mosi),
+                hal::spi::Mode {
+                    polarity: hal::spi::Polarity::IdleHigh,
+                    phase: hal::spi::Phase::CaptureOnSecondTransition,
+                },
+                SPI_FREQ.hz(),
+                clock,
+            );
+            handler::transport::SpiTransport::new(
+                spi,
+                gpiob.pb12.into_push_pull_output(),
+                gpiob.pb1.into_push_pull_output(),
+                gpiob.pb0.into_push_pull_output(),
+            )
+            .unwrap()
+        };
 
         // adc
         let elbow_adc = Adc::adc3(peripherals.ADC3, true, AdcConfig::default());
         let elbow_potentio = gpioa.pa0.into_analog();
 
         // sensor
-        let mut bmx055 = handler::bmx055::IMU::new(i2c, 0.1, CLOCK as f32);
+        let mut fusion = handler::madgwick::Estimated::new(0.1, GYRO_RATE as f32);
+        fusion.set_acc_rejection(GRAVITY, ACC_REJECTION);
+        fusion.set_bias_gain(GYRO_BIAS_GAIN);
+        fusion.set_startup(STARTUP_GAIN, STARTUP_TIME);
+        let mut bmx055 = handler::bmx055::IMU::new(imu_bus, fusion);
+        bmx055.set_acc_settings(ACC_SETTINGS);
+        bmx055.set_gyr_settings(GYR_SETTINGS);
+        if USE_FIFO {
+            bmx055.set_fifo(FIFO_CONFIG);
+        }
+        if SAMPLING == Sampling::DataReady {
+            bmx055.set_interrupts(Some(INTERRUPTS));
+        }
+        if let Some(calibration) = MAG_CALIBRATION {
+            bmx055.set_mag_calibration(calibration);
+        }
+        if let Some(model) = GYRO_TEMP_MODEL {
+            bmx055.set_gyr_temp_model(model);
+        }
         let mut elbow = handler::potentio::Potentiometer::new(elbow_adc, elbow_potentio);
 
-        // initialize
+        // state saved before the last reset, if any
+        let mut backup = handler::backup::BackupSram::new(&peripherals.PWR);
+        let mut blob = [0_u8; SNAPSHOT_SIZE];
+        backup.read(&mut blob);
+
+        // holding the switch at power-up calibrates the accelerometer
         green_led.set_low().unwrap();
-        while switch.is_low().unwrap() {
-            delay.delay_ms(10u8);
+        let calibrate = switch.is_high().unwrap();
+        if calibrate {
+            let calibrated = bmx055
+                .configure(&mut delay, 10)
+                .and_then(|_| calibrate_acc(&mut bmx055, &switch, &mut green_led, &mut delay));
+            if calibrated.is_err() {
+                fault(&mut green_led, &mut delay);
+            }
+        }
+
+        // initialize
+        let initialized = if !calibrate && snapshot::restore(&mut bmx055, &blob).is_ok() {
+            // warm restart, the offsets and attitude are still valid
+            green_led.set_high().unwrap();
+            bmx055.configure(&mut delay, 10)
+        } else {
+            while switch.is_low().unwrap() {
+                delay.delay_ms(10u8);
+            }
+            green_led.set_high().unwrap();
+            bmx055.initialize(&mut delay, 10, INIT_COUNT_IMU)
+        };
+        if initialized.is_err() {
+            fault(&mut green_led, &mut delay);
         }
-        green_led.set_high().unwrap();
-        bmx055.initialize(&mut delay, 10, INIT_COUNT_IMU);
         elbow.initialize(&mut delay, 10, INIT_COUNT_ADC).unwrap();
 
         green_led.set_low().unwrap();
 
         // interrupt
-        let mut timer_interrupt = Timer::tim2(peripherals.TIM2, hal::time::Hertz(CLOCK), clock);
+        let mut timer_interrupt =
+            Timer::tim2(peripherals.TIM2, hal::time::Hertz(SAMPLE_RATE), clock);
+        let mut output_interrupt =
+            Timer::tim3(peripherals.TIM3, hal::time::Hertz(OUTPUT_RATE), clock);
 
-        timer_interrupt.listen(hal::timer::Event::TimeOut);
+        if SAMPLING == Sampling::Timer {
+            timer_interrupt.listen(hal::timer::Event::TimeOut);
+        }
+        output_interrupt.listen(hal::timer::Event::TimeOut);
+
+        // data ready lines, on the rising edge
+        let mut gyro_int = gpiob.pb4.into_floating_input();
+        let mut acc_int = gpiob.pb5.into_floating_input();
+        if SAMPLING == Sampling::DataReady {
+            // RCC has been constrained by the clock setup, only the enable bit is touched here
+            unsafe { (*RCC::ptr()).apb2enr.modify(|_, w| w.syscfgen().set_bit()) };
+            let mut syscfg = peripherals.SYSCFG;
+            let mut exti = peripherals.EXTI;
+            gyro_int.make_interrupt_source(&mut syscfg);
+            gyro_int.trigger_on_edge(&mut exti, Edge::RISING);
+            gyro_int.enable_interrupt(&mut exti);
+            if INTERRUPTS.acc.is_some() {
+                acc_int.make_interrupt_source(&mut syscfg);
+                acc_int.trigger_on_edge(&mut exti, Edge::RISING);
+                acc_int.enable_interrupt(&mut exti);
+            }
+        }
 
         cortex_m::interrupt::free(|cs| {
             *UART_TX.borrow(cs).borrow_mut() = Some(tx);
             *TIMER.borrow(cs).borrow_mut() = Some(timer_interrupt);
+            *OUTPUT_TIMER.borrow(cs).borrow_mut() = Some(output_interrupt);
+            *GYRO_INT.borrow(cs).borrow_mut() = Some(gyro_int);
+            *ACC_INT.borrow(cs).borrow_mut() = Some(acc_int);
+            let now = DWT::cycle_count();
+            LAST_CYCLE.borrow(cs).set(now);
+            LAST_CORRECTION_CYCLE.borrow(cs).set(now);
             *DEVICES.borrow(cs).borrow_mut() = Some(Devices { imu: bmx055, elbow });
         });
 
+        // sampling preempts the transmission of a frame
+        let mut nvic = core_peripherals.NVIC;
         unsafe {
-            hal::stm32::NVIC::unmask(hal::stm32::Interrupt::TIM2);
+            nvic.set_priority(hal::stm32::Interrupt::TIM2, 1 << 4);
+            nvic.set_priority(hal::stm32::Interrupt::EXTI4, 1 << 4);
+            nvic.set_priority(hal::stm32::Interrupt::EXTI9_5, 1 << 4);
+            nvic.set_priority(hal::stm32::Interrupt::TIM3, 2 << 4);
+            match SAMPLING {
+                Sampling::Timer => hal::stm32::NVIC::unmask(hal::stm32::Interrupt::TIM2),
+                Sampling::DataReady => {
+                    hal::stm32::NVIC::unmask(hal::stm32::Interrupt::EXTI4);
+                    hal::stm32::NVIC::unmask(hal::stm32::Interrupt::EXTI9_5);
+                }
+            }
+            hal::stm32::NVIC::unmask(hal::stm32::Interrupt::TIM3);
         };
+        if SAMPLING == Sampling::DataReady && USE_FIFO {
+            // a watermark reached before the EXTI was enabled holds the line high without an
+            // edge, so the first drain is started by hand
+            hal::stm32::NVIC::pend(hal::stm32::Interrupt::EXTI4);
+        }
 
+        // the switch tares the arm's zero pose on each press
+        let mut last_switch = switch.is_high().unwrap();
+        let mut ticks = 0_u8;
+        let mut snapshot_ticks = 0_u8;
         loop {
-            green_led.toggle().unwrap();
-            delay.delay_ms(250u8);
+            let pressed = switch.is_high().unwrap();
+            if pressed && !last_switch {
+                cortex_m::interrupt::free(|cs| {
+                    if let Some(ref mut dev) = DEVICES.borrow(cs).borrow_mut().deref_mut() {
+                        dev.imu.tare(TARE_MODE);
+                    }
+                });
+            }
+            last_switch = pressed;
+
+            // a failing IMU blinks the LED fast
+            let failing = cortex_m::interrupt::free(|cs| IMU_FAULT.borrow(cs).get());
+            let blink_ticks = if failing {
+                FAULT_BLINK_TICKS
+            } else {
+                LED_BLINK_TICKS
+            };
+            ticks += 1;
+            if ticks >= blink_ticks {
+                ticks = 0;
+                green_led.toggle().unwrap();
+            }
+
+            snapshot_ticks += 1;
+            if snapshot_ticks >= SNAPSHOT_TICKS {
+                snapshot_ticks = 0;
+                cortex_m::interrupt::free(|cs| {
+                    if let Some(dev) = DEVICES.borrow(cs).borrow().as_ref() {
+                        snapshot::save(&dev.imu, &mut blob).unwrap();
+                    }
+                });
+                backup.write(&blob);
+            }
+            delay.delay_ms(SWITCH_POLL_MS);
         }
     }
 
     loop {}
 }
 
+/// Six-position accelerometer calibration. For each position the device is laid still with
+/// one axis pointing up or down and the switch pressed; the LED stays on while averaging.
+fn calibrate_acc(
+    imu: &mut Imu,
+    switch: &PA10<Input<PullDown>>,
+    led: &mut PA5<Output<PushPull>>,
+    delay: &mut Delay,
+) -> Result<(), Bmx055Error<<ImuBus as Transport>::Error>> {
+    let mut positions = handler::calibration::SixPosition::new();
+    while positions.remaining() > 0 {
+        while switch.is_high().unwrap() {
+            delay.delay_ms(SWITCH_POLL_MS);
+        }
+        while switch.is_low().unwrap() {
+            delay.delay_ms(SWITCH_POLL_MS);
+        }
+        led.set_high().unwrap();
+        positions.add(imu.average_acc(delay, 5, ACC_CALIBRATION_COUNT)?);
+        led.set_low().unwrap();
+    }
+
+    if let Some(calibration) = positions.solve(GRAVITY, ACC_CROSS_AXIS) {
+        imu.set_acc_calibration(calibration);
+    }
+    while switch.is_high().unwrap() {
+        delay.delay_ms(SWITCH_POLL_MS);
+    }
+    Ok(())
+}
+
+/// Stops at a sensor that cannot be set up, blinking the LED fast.
+fn fault(led: &mut PA5<Output<PushPull>>, delay: &mut Delay) -> ! {
+    loop {
+        led.toggle().unwrap();
+        delay.delay_ms(SWITCH_POLL_MS * FAULT_BLINK_TICKS);
+    }
+}
+
 #[interrupt]
 fn TIM2() {
     cortex_m::interrupt::free(|cs| {
         if let Some(ref mut timer) = TIMER.borrow(cs).borrow_mut().deref_mut() {
             timer.clear_interrupt(hal::timer::Event::TimeOut);
+        }
+        if USE_FIFO {
+            drain_fifo(cs);
+        } else {
+            sample_gyro(cs);
+        }
+    });
+}
 
-            if let Some(ref mut tx) = UART_TX.borrow(cs).borrow_mut().deref_mut() {
-                if let Some(ref mut dev) = DEVICES.borrow(cs).borrow_mut().deref_mut() {
-                    dev.imu.update();
-                    let angle = match dev.elbow.read_rad() {
-                        Ok(val) => val,
-                        Err(_) => 0.0_f32,
-                    };
-                    handler::serial::transmit_quaternion(tx, dev.imu.imu_data, &HEADER);
-                    handler::serial::transmit_base(tx, &angle.to_le_bytes());
-                }
-            }
+#[interrupt]
+fn EXTI4() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(ref mut pin) = GYRO_INT.borrow(cs).borrow_mut().deref_mut() {
+            pin.clear_interrupt_pending_bit();
+        }
+        let ok = if USE_FIFO {
+            drain_fifo(cs)
+        } else {
+            sample_gyro(cs)
+        };
+        retry_latched(cs, ok, &GYRO_INT_FAILURES, hal::stm32::Interrupt::EXTI4);
+    });
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(ref mut pin) = ACC_INT.borrow(cs).borrow_mut().deref_mut() {
+            pin.clear_interrupt_pending_bit();
+        }
+        let ok = sample_acc(cs);
+        retry_latched(cs, ok, &ACC_INT_FAILURES, hal::stm32::Interrupt::EXTI9_5);
+    });
+}
+
+/// Follows up a read started by an INT line. After a failed read the line stays high with the
+/// data or the watermark still pending, so no new edge comes: the interrupt is pended again,
+/// and after `INT_RETRIES` failures in a row TIM2 takes over the sampling.
+fn retry_latched(
+    cs: &CriticalSection,
+    ok: bool,
+    failures: &Mutex<Cell<u8>>,
+    line: hal::stm32::Interrupt,
+) {
+    let failures = failures.borrow(cs);
+    if ok {
+        failures.set(0);
+    } else if POLLING.borrow(cs).get() {
+        // a handler pended before the fall back, TIM2 retries from now on
+    } else if failures.get() < INT_RETRIES {
+        failures.set(failures.get() + 1);
+        hal::stm32::NVIC::pend(line);
+    } else {
+        POLLING.borrow(cs).set(true);
+        hal::stm32::NVIC::mask(hal::stm32::Interrupt::EXTI4);
+        hal::stm32::NVIC::mask(hal::stm32::Interrupt::EXTI9_5);
+        if let Some(ref mut timer) = TIMER.borrow(cs).borrow_mut().deref_mut() {
+            timer.listen(hal::timer::Event::TimeOut);
         }
+        unsafe { hal::stm32::NVIC::unmask(hal::stm32::Interrupt::TIM2) };
+    }
+}
+
+/// Fuses the buffered samples, timed by the sensor. Overruns are counted by the driver.
+/// Returns whether the read succeeded.
+fn drain_fifo(cs: &CriticalSection) -> bool {
+    if let Some(ref mut dev) = DEVICES.borrow(cs).borrow_mut().deref_mut() {
+        let result = dev.imu.drain_fifo();
+        IMU_FAULT.borrow(cs).set(result.is_err());
+        return result.is_ok();
+    }
+    true
+}
+
+/// Propagates one gyro sample. Polling by timer also corrects every `CORRECTION_DIVIDER`
+/// samples, while on data ready the accelerometer raises its own interrupt. Returns whether
+/// the reads succeeded.
+fn sample_gyro(cs: &CriticalSection) -> bool {
+    let now = DWT::cycle_count();
+    let elapsed = now.wrapping_sub(LAST_CYCLE.borrow(cs).replace(now));
+    let mut ok = true;
+    if let Some(ref mut dev) = DEVICES.borrow(cs).borrow_mut().deref_mut() {
+        let result = dev.imu.update_gyro_dt(elapsed as f32 / HCLK as f32);
+        IMU_FAULT.borrow(cs).set(result.is_err());
+        ok = result.is_ok();
+    }
+
+    if POLLING.borrow(cs).get() {
+        let ticks = GYRO_TICKS.borrow(cs).get() + 1;
+        let correct = ticks >= CORRECTION_DIVIDER;
+        GYRO_TICKS.borrow(cs).set(if correct { 0 } else { ticks });
+        if correct {
+            ok &= sample_acc(cs);
+        }
+    }
+    ok
+}
+
+/// Corrects with one accelerometer sample. Returns whether the reads succeeded.
+fn sample_acc(cs: &CriticalSection) -> bool {
+    let now = DWT::cycle_count();
+    let elapsed = now.wrapping_sub(LAST_CORRECTION_CYCLE.borrow(cs).replace(now));
+    if let Some(ref mut dev) = DEVICES.borrow(cs).borrow_mut().deref_mut() {
+        let result = dev.imu.correct_dt(elapsed as f32 / HCLK as f32);
+        IMU_FAULT.borrow(cs).set(result.is_err());
+        return result.is_ok();
+    }
+    true
+}
+
+#[interrupt]
+fn TIM3() {
+    // the frame is gathered in a critical section but sent outside of it, so that TIM2 keeps
+    // sampling while the UART is busy
+    let (frame, tx) = cortex_m::interrupt::free(|cs| {
+        if let Some(ref mut timer) = OUTPUT_TIMER.borrow(cs).borrow_mut().deref_mut() {
+            timer.clear_interrupt(hal::timer::Event::TimeOut);
+        }
+
+        let frame = match DEVICES.borrow(cs).borrow_mut().deref_mut() {
+            Some(ref mut dev) => {
+                let angle = match dev.elbow.read_rad() {
+                    Ok(val) => val,
+                    Err(_) => 0.0_f32,
+                };
+                Some((
+                    dev.imu.get_quaternion(),
+                    angle,
+                    dev.imu.is_stationary(),
+                    dev.imu.get_earth_acc(),
+                ))
+            }
+            None => None,
+        };
+        (frame, UART_TX.borrow(cs).borrow_mut().take())
     });
+
+    if let Some(mut tx) = tx {
+        if let Some((q, angle, stationary, linear_acc)) = frame {
+            handler::serial::transmit_quaternion(&mut tx, q, &HEADER);
+            handler::serial::transmit_base(&mut tx, &angle.to_le_bytes());
+            handler::serial::transmit_base(&mut tx, &[stationary as u8]);
+            if STREAM_LINEAR_ACC {
+                handler::serial::transmit_vector(&mut tx, linear_acc, &[]);
+            }
+        }
+        cortex_m::interrupt::free(|cs| {
+            *UART_TX.borrow(cs).borrow_mut() = Some(tx);
+        });
+    }
 }
@@ -0,0 +1,392 @@
+//! Sensor calibrations applied to raw readings before fusion.
+
+use super::matrix::invert3;
+use super::snapshot::{Reader, Snapshot, Writer};
+
+const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+fn correct(offset: &[f32; 3], w: &[[f32; 3]; 3], m: [f32; 3]) -> [f32; 3] {
+    let v = [m[0] - offset[0], m[1] - offset[1], m[2] - offset[2]];
+    [
+        w[0][0] * v[0] + w[0][1] * v[1] + w[0][2] * v[2],
+        w[1][0] * v[0] + w[1][1] * v[1] + w[1][2] * v[2],
+        w[2][0] * v[0] + w[2][1] * v[1] + w[2][2] * v[2],
+    ]
+}
+
+/// Hard- and soft-iron correction of a magnetometer: `matrix * (m - offset)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MagCalibration {
+    pub offset: [f32; 3],
+    pub matrix: [[f32; 3]; 3],
+}
+
+impl Default for MagCalibration {
+    fn default() -> Self {
+        Self::new([0.0; 3], IDENTITY)
+    }
+}
+
+impl MagCalibration {
+    pub const fn new(offset: [f32; 3], matrix: [[f32; 3]; 3]) -> Self {
+        Self { offset, matrix }
+    }
+
+    pub fn apply(&self, m: [f32; 3]) -> [f32; 3] {
+        correct(&self.offset, &self.matrix, m)
+    }
+}
+
+impl Snapshot for MagCalibration {
+    const SIZE: usize = 48;
+
+    fn save(&self, w: &mut Writer) {
+        for v in self.offset.iter().chain(self.matrix.iter().flatten()) {
+            w.f32(*v);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) {
+        for v in self
+            .offset
+            .iter_mut()
+            .chain(self.matrix.iter_mut().flatten())
+        {
+            *v = r.f32();
+        }
+    }
+}
+
+/// Bias, scale and optionally cross-axis correction of an accelerometer:
+/// `matrix * (a - bias)`, the matrix being diagonal without cross-axis terms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AccCalibration {
+    pub bias: [f32; 3],
+    pub matrix: [[f32; 3]; 3],
+}
+
+impl Default for AccCalibration {
+    fn default() -> Self {
+        Self::new([0.0; 3], IDENTITY)
+    }
+}
+
+impl AccCalibration {
+    pub const fn new(bias: [f32; 3], matrix: [[f32; 3]; 3]) -> Self {
+        Self { bias, matrix }
+    }
+
+    pub fn apply(&self, a: [f32; 3]) -> [f32; 3] {
+        correct(&self.bias, &self.matrix, a)
+    }
+}
+
+impl Snapshot for AccCalibration {
+    const SIZE: usize = 48;
+
+    fn save(&self, w: &mut Writer) {
+        for v in self.bias.iter().chain(self.matrix.iter().flatten()) {
+            w.f32(*v);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) {
+        for v in self.bias.iter_mut().chain(self.matrix.iter_mut().flatten()) {
+            *v = r.f32();
+        }
+    }
+}
+
+/// Gyroscope bias drifting linearly with the die temperature:
+/// `offset + slope * (temperature - reference)`, in deg/s with the temperature in degrees Celsius.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GyroTempModel {
+    pub reference: f32,
+    pub offset: [f32; 3],
+    pub slope: [f32; 3],
+}
+
+impl GyroTempModel {
+    pub const fn new(reference: f32, offset: [f32; 3], slope: [f32; 3]) -> Self {
+        Self {
+            reference,
+            offset,
+            slope,
+        }
+    }
+
+    pub fn bias(&self, temperature: f32) -> [f32; 3] {
+        let dt = temperature - self.reference;
+        [
+            self.offset[0] + self.slope[0] * dt,
+            self.offset[1] + self.slope[1] * dt,
+            self.offset[2] + self.slope[2] * dt,
+        ]
+    }
+}
+
+impl Snapshot for GyroTempModel {
+    const SIZE: usize = 28;
+
+    fn save(&self, w: &mut Writer) {
+        w.f32(self.reference);
+        for v in self.offset.iter().chain(self.slope.iter()) {
+            w.f32(*v);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) {
+        self.reference = r.f32();
+        for v in self.offset.iter_mut().chain(self.slope.iter_mut()) {
+            *v = r.f32();
+        }
+    }
+}
+
+/// Least-squares fit of a `GyroTempModel` to gyroscope rates recorded at rest while the board
+/// warms up.
+///
+/// Only running sums are kept, so a recording of any length fits in a few words. The sums are
+/// taken around the first temperature to keep them well conditioned in single precision.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GyroTempFit {
+    count: u32,
+    reference: f32,
+    sum_t: f32,
+    sum_tt: f32,
+    sum_g: [f32; 3],
+    sum_tg: [f32; 3],
+}
+
+impl GyroTempFit {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a rate (deg/s, before any offset is removed) read at `temperature`.
+    pub fn add(&mut self, temperature: f32, gyr: [f32; 3]) {
+        if self.count == 0 {
+            self.reference = temperature;
+        }
+        let t = temperature - self.reference;
+        self.count += 1;
+        self.sum_t += t;
+        self.sum_tt += t * t;
+        for (i, g) in gyr.iter().enumerate() {
+            self.sum_g[i] += g;
+            self.sum_tg[i] += t * g;
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Spread of the recorded temperatures in degrees Celsius (standard deviation).
+    pub fn spread(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let n = self.count as f32;
+        let mean = self.sum_t / n;
+        libm::sqrtf((self.sum_tt / n - mean * mean).max(0.0))
+    }
+
+    /// Solves the model referenced to the mean temperature of the recording. Fails without at
+    /// least two distinct temperatures.
+    pub fn solve(&self) -> Option<GyroTempModel> {
+        let n = self.count as f32;
+        let variance = n * self.sum_tt - self.sum_t * self.sum_t;
+        if self.count < 2 || variance <= f32::EPSILON * n * self.sum_tt {
+            return None;
+        }
+        let mean_t = self.sum_t / n;
+        let mut offset = [0.0; 3];
+        let mut slope = [0.0; 3];
+        for i in 0..3 {
+            slope[i] = (n * self.sum_tg[i] - self.sum_t * self.sum_g[i]) / variance;
+            // the fitted line passes through the means
+            offset[i] = self.sum_g[i] / n;
+        }
+        Some(GyroTempModel::new(self.reference + mean_t, offset, slope))
+    }
+}
+
+/// Averaged accelerometer readings taken at rest with each axis pointing up and down in turn.
+///
+/// The orientation of a reading is recognized from its dominant axis, so the six positions can
+/// be visited in any order; a later reading of the same position replaces the earlier one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SixPosition {
+    // +x, -x, +y, -y, +z, -z up
+    readings: [Option<[f32; 3]>; 6],
+}
+
+impl SixPosition {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Stores a reading and returns the index of the position it was taken in.
+    pub fn add(&mut self, a: [f32; 3]) -> usize {
+        let mut axis = 0;
+        for i in 1..3 {
+            if a[i].abs() > a[axis].abs() {
+                axis = i;
+            }
+        }
+        let index = axis * 2 + if a[axis] < 0.0 { 1 } else { 0 };
+        self.readings[index] = Some(a);
+        index
+    }
+
+    /// Number of positions still missing.
+    pub fn remaining(&self) -> usize {
+        self.readings.iter().filter(|r| r.is_none()).count()
+    }
+
+    /// Solves the calibration mapping the readings onto `gravity`, with cross-axis terms when
+    /// `cross_axis` is set. Needs all six positions.
+    pub fn solve(&self, gravity: f32, cross_axis: bool) -> Option<AccCalibration> {
+        let mut r = [[0.0_f32; 3]; 6];
+        for (dst, src) in r.iter_mut().zip(self.readings.iter()) {
+            *dst = (*src)?;
+        }
+
+        // the true vectors sum to zero over the six positions, so the bias is their mean
+        let mut bias = [0.0_f32; 3];
+        for reading in r.iter() {
+            for i in 0..3 {
+                bias[i] += reading[i] / 6.0;
+            }
+        }
+
+        // column j of the inverse correction is the reading of 2 g along axis j
+        let mut sensitivity = [[0.0_f32; 3]; 3];
+        for j in 0..3 {
+            for i in 0..3 {
+                if cross_axis || i == j {
+                    sensitivity[i][j] = (r[2 * j][i] - r[2 * j + 1][i]) / (2.0 * gravity);
+                }
+            }
+        }
+
+        Some(AccCalibration::new(bias, invert3(&sensitivity)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_linear_drift() {
+        let mut fit = GyroTempFit::new();
+        for k in 0..200 {
+            // warming from 24 to 34 degrees with a little noise
+            let t = 24.0 + 10.0 * k as f32 / 199.0;
+            let noise = if k % 2 == 0 { 0.01 } else { -0.01 };
+            let g = [
+                0.5 + 0.02 * (t - 30.0) + noise,
+                -0.3 - 0.05 * (t - 30.0),
+                0.1,
+            ];
+            fit.add(t, g);
+        }
+        assert_eq!(fit.count(), 200);
+
+        let model = fit.solve().unwrap();
+        assert!((model.reference - 29.0).abs() < 1e-3);
+        let at_30 = model.bias(30.0);
+        let expected = [0.5, -0.3, 0.1];
+        for i in 0..3 {
+            assert!((at_30[i] - expected[i]).abs() < 1e-3, "{:?}", at_30);
+        }
+        assert!((model.slope[0] - 0.02).abs() < 1e-3);
+        assert!((model.slope[1] + 0.05).abs() < 1e-4);
+        assert!(model.slope[2].abs() < 1e-5);
+    }
+
+    #[test]
+    fn needs_a_temperature_change() {
+        let mut fit = GyroTempFit::new();
+        assert!(fit.solve().is_none());
+        for _ in 0..50 {
+            fit.add(25.0, [0.1, 0.2, 0.3]);
+        }
+        assert_eq!(fit.spread(), 0.0);
+        assert!(fit.solve().is_none());
+    }
+
+    #[test]
+    fn default_is_passthrough() {
+        assert_eq!(
+            MagCalibration::default().apply([1.0, -2.0, 3.0]),
+            [1.0, -2.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn removes_offset_then_scales() {
+        let cal = MagCalibration::new(
+            [10.0, 0.0, -5.0],
+            [[0.5, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 1.0, 1.0]],
+        );
+        assert_eq!(cal.apply([12.0, 1.0, -4.0]), [1.0, 2.0, 2.0]);
+    }
+
+    // readings of a sensor with bias, per-axis scale and a little cross-axis coupling
+    fn readings(gravity: f32) -> [[f32; 3]; 6] {
+        let bias = [0.3, -0.2, 0.5];
+        let sens = [[1.02, 0.01, 0.0], [0.0, 0.97, -0.02], [0.015, 0.0, 1.05]];
+        let mut out = [[0.0; 3]; 6];
+        for (k, reading) in out.iter_mut().enumerate() {
+            let mut t = [0.0; 3];
+            t[k / 2] = if k % 2 == 0 { gravity } else { -gravity };
+            for i in 0..3 {
+                reading[i] = bias[i] + sens[i][0] * t[0] + sens[i][1] * t[1] + sens[i][2] * t[2];
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn six_positions_in_any_order() {
+        let g = 9.80665;
+        let mut six = SixPosition::new();
+        for (k, reading) in readings(g).iter().enumerate().rev() {
+            assert_eq!(six.add(*reading), k);
+        }
+        assert_eq!(six.remaining(), 0);
+
+        let cal = six.solve(g, true).unwrap();
+        for (k, reading) in readings(g).iter().enumerate() {
+            let a = cal.apply(*reading);
+            let mut expected = [0.0; 3];
+            expected[k / 2] = if k % 2 == 0 { g } else { -g };
+            for i in 0..3 {
+                assert!(
+                    (a[i] - expected[i]).abs() < 1e-4,
+                    "{:?} != {:?}",
+                    a,
+                    expected
+                );
+            }
+        }
+
+        // without cross-axis terms only the diagonal is corrected
+        let cal = six.solve(g, false).unwrap();
+        assert_eq!(cal.matrix[0][1], 0.0);
+        assert!((cal.apply(readings(g)[4])[2] - g).abs() < 1e-4);
+    }
+
+    #[test]
+    fn needs_all_positions() {
+        let mut six = SixPosition::new();
+        for reading in readings(1.0).iter().take(5) {
+            six.add(*reading);
+        }
+        assert_eq!(six.remaining(), 1);
+        assert!(six.solve(1.0, false).is_none());
+    }
+}
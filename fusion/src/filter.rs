@@ -0,0 +1,94 @@
+use super::quaternion::Quaternion;
+
+pub trait OrientationFilter {
+    /// Feeds one accelerometer (any unit) and gyroscope (degree/sec) sample.
+    fn update_imu(&mut self, a_x: f32, a_y: f32, a_z: f32, g_x: f32, g_y: f32, g_z: f32);
+
+    /// Same as `update_imu`, with the time elapsed since the previous sample (sec).
+    fn update_imu_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        dt: f32,
+    );
+
+    /// Same as `update_imu`, with a magnetometer sample (any unit) correcting the heading.
+    /// Filters without magnetometer support ignore it.
+    fn update_marg(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        _m_x: f32,
+        _m_y: f32,
+        _m_z: f32,
+    ) {
+        self.update_imu(a_x, a_y, a_z, g_x, g_y, g_z);
+    }
+
+    /// Same as `update_marg`, with the time elapsed since the previous sample (sec).
+    fn update_marg_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        _m_x: f32,
+        _m_y: f32,
+        _m_z: f32,
+        dt: f32,
+    ) {
+        self.update_imu_dt(a_x, a_y, a_z, g_x, g_y, g_z, dt);
+    }
+
+    /// Propagates the orientation with a gyroscope sample (degree/sec) alone, for sampling the
+    /// gyroscope faster than the accelerometer.
+    fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, dt: f32);
+
+    /// Applies the accelerometer correction alone, with the time elapsed since the previous
+    /// correction (sec).
+    fn correct_imu_dt(&mut self, a_x: f32, a_y: f32, a_z: f32, dt: f32);
+
+    /// Same as `correct_imu_dt`, with a magnetometer sample (any unit) correcting the heading.
+    /// Filters without magnetometer support ignore it.
+    fn correct_marg_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        _m_x: f32,
+        _m_y: f32,
+        _m_z: f32,
+        dt: f32,
+    ) {
+        self.correct_imu_dt(a_x, a_y, a_z, dt);
+    }
+
+    fn get_quaternion(&self) -> Quaternion;
+
+    /// Overrides the estimated orientation, e.g. with an initial attitude.
+    fn set_quaternion(&mut self, q: Quaternion);
+
+    /// Starts from the attitude given by gravity and, when available, the magnetic field.
+    fn set_initial(&mut self, acc: [f32; 3], mag: Option<[f32; 3]>) {
+        self.set_quaternion(match mag {
+            Some(m) => Quaternion::from_gravity_and_mag(acc, m),
+            None => Quaternion::from_gravity(acc),
+        });
+    }
+
+    /// Drops the estimated orientation and any internal state, keeping the parameters.
+    fn reset(&mut self);
+
+    /// Returns the gyro bias (rad/sec) currently removed from the measurements.
+    fn get_gyro_bias(&self) -> [f32; 3];
+}
@@ -0,0 +1,211 @@
+use core::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use super::real::Real;
+
+const FRAC_BITS: u32 = 16;
+const ONE: i32 = 1 << FRAC_BITS;
+
+/// Signed Q16.16 fixed point number for targets without an FPU.
+///
+/// Arithmetic saturates instead of wrapping, and division by zero saturates to the largest
+/// magnitude of the dividend's sign.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q16(pub i32);
+
+impl Q16 {
+    pub const fn from_bits(bits: i32) -> Self {
+        Q16(bits)
+    }
+
+    pub const fn to_bits(self) -> i32 {
+        self.0
+    }
+}
+
+fn saturate(v: i64) -> i32 {
+    if v > i32::MAX as i64 {
+        i32::MAX
+    } else if v < i32::MIN as i64 {
+        i32::MIN
+    } else {
+        v as i32
+    }
+}
+
+fn isqrt(v: u64) -> u64 {
+    let mut rem = v;
+    let mut root = 0_u64;
+    let mut bit = 1_u64 << 62;
+    while bit > rem {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+impl Add for Q16 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Q16(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Q16 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Q16(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Mul for Q16 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Q16(saturate((self.0 as i64 * rhs.0 as i64) >> FRAC_BITS))
+    }
+}
+
+impl Div for Q16 {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        if rhs.0 == 0 {
+            return Q16(if self.0 < 0 { i32::MIN } else { i32::MAX });
+        }
+        Q16(saturate(((self.0 as i64) << FRAC_BITS) / rhs.0 as i64))
+    }
+}
+
+impl Neg for Q16 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Q16(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Q16 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Q16 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl MulAssign for Q16 {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl Real for Q16 {
+    fn from_f32(v: f32) -> Self {
+        // `as` saturates out of range values
+        Q16((v * ONE as f32) as i32)
+    }
+
+    fn to_f32(self) -> f32 {
+        self.0 as f32 / ONE as f32
+    }
+
+    fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Q16(0);
+        }
+        Q16(isqrt((self.0 as u64) << FRAC_BITS) as i32)
+    }
+
+    fn abs(self) -> Self {
+        Q16(self.0.saturating_abs())
+    }
+
+    fn zero() -> Self {
+        Q16(0)
+    }
+
+    fn one() -> Self {
+        Q16(ONE)
+    }
+
+    fn half() -> Self {
+        Q16::from_bits(ONE / 2)
+    }
+
+    fn two() -> Self {
+        Q16::from_bits(2 * ONE)
+    }
+
+    fn four() -> Self {
+        Q16::from_bits(4 * ONE)
+    }
+
+    fn deg_to_rad() -> Self {
+        Q16::from_bits(1143) // 0.0174533 * 2^16, truncated as by from_f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Q16, b: f32) -> bool {
+        (a.to_f32() - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = Q16::from_f32(1.5);
+        let b = Q16::from_f32(-0.25);
+        assert!(close(a + b, 1.25));
+        assert!(close(a - b, 1.75));
+        assert!(close(a * b, -0.375));
+        assert!(close(a / b, -6.0));
+        assert!(close(-a, -1.5));
+        assert!(close(b.abs(), 0.25));
+    }
+
+    #[test]
+    fn square_root() {
+        assert!(close(Q16::from_f32(2.0).sqrt(), core::f32::consts::SQRT_2));
+        assert!(close(Q16::from_f32(0.01).sqrt(), 0.1));
+        assert!(close(Q16::from_f32(10000.0).sqrt(), 100.0));
+        assert!(close(Q16::from_f32(4.0).inv_sqrt(), 0.5));
+        assert_eq!(Q16::zero().sqrt(), Q16::zero());
+    }
+
+    #[test]
+    fn constants_match_conversion() {
+        for (c, v) in [
+            (Q16::half(), 0.5),
+            (Q16::two(), 2.0),
+            (Q16::four(), 4.0),
+            (Q16::deg_to_rad(), 0.0174533),
+        ]
+        .iter()
+        {
+            assert_eq!(*c, Q16::from_f32(*v));
+        }
+    }
+
+    #[test]
+    fn saturates() {
+        let big = Q16::from_f32(30000.0);
+        assert_eq!((big + big).to_bits(), i32::MAX);
+        assert_eq!((big * -big).to_bits(), i32::MIN);
+        assert_eq!((big / Q16::zero()).to_bits(), i32::MAX);
+    }
+}
@@ -0,0 +1,21 @@
+//! Orientation math and fusion filters shared by the firmware and the host tools.
+//!
+//! The crate is `no_std` unless the `std` feature is enabled. All math goes through `libm` in
+//! either case, so the host computes exactly what the device does.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// the filters take sensor axes as separate arguments
+#![allow(clippy::too_many_arguments)]
+
+pub mod calibration;
+pub mod filter;
+pub mod fixed;
+pub mod madgwick;
+pub mod mahony;
+mod matrix;
+pub mod mekf;
+pub mod motion;
+pub mod quaternion;
+pub mod real;
+pub mod snapshot;
+pub mod stationary;
+pub mod tare;
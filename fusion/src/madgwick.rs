@@ -0,0 +1,901 @@
+use super::filter::OrientationFilter;
+use super::quaternion::Quaternion;
+use super::real::Real;
+use super::snapshot::{Reader, Snapshot, Writer};
+
+// a zero vector is left as is, 1 / sqrt(0) being infinite for the exact types
+
+fn normalize3d<N: Real>(v: &mut [N; 3]) {
+    let squared = v[0] * v[0] + v[1] * v[1] + v[2] * v[2];
+    if squared == N::zero() {
+        return;
+    }
+    let norm = squared.inv_sqrt();
+    v[0] *= norm;
+    v[1] *= norm;
+    v[2] *= norm;
+}
+
+fn normalize4d<N: Real>(q: &mut Quaternion<N>) {
+    let squared = q.dot(q);
+    if squared == N::zero() {
+        return;
+    }
+    *q = *q * squared.inv_sqrt();
+}
+
+fn rotate_and_scalevector<N: Real>(q: &Quaternion<N>, _2d: [N; 3]) -> [N; 3] {
+    let Quaternion {
+        w: q0,
+        x: q1,
+        y: q2,
+        z: q3,
+    } = *q;
+    let [_2dx, _2dy, _2dz] = _2d;
+    let half = N::half();
+    [
+        _2dx * (half - q2 * q2 - q3 * q3) + _2dy * (q0 * q3 + q1 * q2) + _2dz * (q1 * q3 - q0 * q2),
+        _2dx * (q1 * q2 - q0 * q3) + _2dy * (half - q1 * q1 - q3 * q3) + _2dz * (q0 * q1 + q2 * q3),
+        _2dx * (q0 * q2 + q1 * q3) + _2dy * (q2 * q3 - q0 * q1) + _2dz * (half - q1 * q1 - q2 * q2),
+    ]
+}
+
+fn add_gradient_descent_step<N: Real>(
+    q: &Quaternion<N>,
+    _2d: [N; 3],
+    m: [N; 3],
+    s: &mut Quaternion<N>,
+) {
+    let Quaternion {
+        w: q0,
+        x: q1,
+        y: q2,
+        z: q3,
+    } = *q;
+    let [_2dx, _2dy, _2dz] = _2d;
+    let two = N::two();
+
+    let r = rotate_and_scalevector(q, _2d);
+    let f0 = r[0] - m[0];
+    let f1 = r[1] - m[1];
+    let f2 = r[2] - m[2];
+
+    s.w +=
+        (_2dy * q3 - _2dz * q2) * f0 + (-_2dx * q3 + _2dz * q1) * f1 + (_2dx * q2 - _2dy * q1) * f2;
+    s.x += (_2dy * q2 + _2dz * q3) * f0
+        + (_2dx * q2 - two * _2dy * q1 + _2dz * q0) * f1
+        + (_2dx * q3 - _2dy * q0 - two * _2dz * q1) * f2;
+    s.y += (-two * _2dx * q2 + _2dy * q1 - _2dz * q0) * f0
+        + (_2dx * q1 + _2dz * q3) * f1
+        + (_2dx * q0 + _2dy * q3 - two * _2dz * q2) * f2;
+    s.z += (-two * _2dx * q3 + _2dy * q0 + _2dz * q1) * f0
+        + (-_2dx * q0 - two * _2dy * q3 + _2dz * q2) * f1
+        + (_2dx * q1 + _2dy * q2) * f2;
+}
+
+/// Returns (2 bxy, 2 bz), the reference direction of earth's magnetic field.
+fn compensate_magnetic_distortion<N: Real>(q: &Quaternion<N>, m: [N; 3]) -> (N, N) {
+    let h = rotate_and_scalevector(&q.conjugate(), m);
+    let four = N::four();
+    (four * (h[0] * h[0] + h[1] * h[1]).sqrt(), four * h[2])
+}
+
+fn orientation_change_from_gyro<N: Real>(q: &Quaternion<N>, g: [N; 3]) -> Quaternion<N> {
+    *q * Quaternion::new(N::zero(), g[0], g[1], g[2]) * N::half()
+}
+
+/// Madgwick filter, generic over the numeric type so that the same code runs in f32 on the
+/// firmware, in f64 on the host and in fixed point (`fixed::Q16`) on targets without an FPU.
+#[derive(Clone, Copy)]
+pub struct Estimated<N = f32> {
+    gain: N,
+    active_gain: N,
+    gravity: N,
+    rejection: N,
+    q: Quaternion<N>,
+    zeta: N,
+    startup_gain: N,
+    startup_time: N,
+    startup_remaining: N,
+    bias_x: N,
+    bias_y: N,
+    bias_z: N,
+    dt: N,
+}
+
+impl<N: Real> Default for Estimated<N> {
+    fn default() -> Self {
+        Self {
+            gain: N::from_f32(0.1),
+            active_gain: N::from_f32(0.1),
+            gravity: N::one(),
+            rejection: N::zero(),
+            q: Quaternion::identity(),
+            zeta: N::zero(),
+            startup_gain: N::zero(),
+            startup_time: N::zero(),
+            startup_remaining: N::zero(),
+            bias_x: N::zero(),
+            bias_y: N::zero(),
+            bias_z: N::zero(),
+            dt: N::from_f32(1.0 / 512.0),
+        }
+    }
+}
+
+impl<N: Real> Estimated<N> {
+    pub fn new(gain: N, freq: N) -> Self {
+        Self {
+            gain,
+            active_gain: gain,
+            dt: N::one() / freq,
+            ..Default::default()
+        }
+    }
+
+    /// Scales the correction gain down linearly as the accelerometer magnitude departs from
+    /// `gravity`, skipping the correction entirely once the relative deviation reaches
+    /// `threshold`. A non-positive `threshold` keeps the gain constant.
+    pub fn set_acc_rejection(&mut self, gravity: N, threshold: N) {
+        self.gravity = gravity;
+        self.rejection = threshold;
+    }
+
+    /// Returns the correction gain used by the latest update.
+    pub fn get_gain(&self) -> N {
+        self.active_gain
+    }
+
+    /// Enables online gyro bias estimation, `zeta` being the rate (rad/sec^2) at which the bias
+    /// follows the gyro error seen by the correction step. Zero disables it.
+    pub fn set_bias_gain(&mut self, zeta: N) {
+        self.zeta = zeta;
+    }
+
+    /// Returns the estimated gyro bias (rad/sec).
+    pub fn get_gyro_bias(&self) -> [N; 3] {
+        [self.bias_x, self.bias_y, self.bias_z]
+    }
+
+    fn estimate_bias(&mut self, s: &Quaternion<N>, dt: N) {
+        if self.zeta <= N::zero() {
+            return;
+        }
+        // gyro error is 2 * q^-1 * s
+        let e = self.q.conjugate() * *s * N::two();
+        self.bias_x += self.zeta * e.x * dt;
+        self.bias_y += self.zeta * e.y * dt;
+        self.bias_z += self.zeta * e.z * dt;
+    }
+
+    /// Uses `gain` instead of the normal one for the first `time` seconds of updates after
+    /// construction or reset, so that the estimate converges quickly at startup.
+    pub fn set_startup(&mut self, gain: N, time: N) {
+        self.startup_gain = gain;
+        self.startup_time = time;
+        self.startup_remaining = time;
+    }
+
+    /// Starts from the attitude given by gravity and, when available, the magnetic field.
+    pub fn set_initial(&mut self, acc: [f32; 3], mag: Option<[f32; 3]>) {
+        let q = match mag {
+            Some(m) => Quaternion::from_gravity_and_mag(acc, m),
+            None => Quaternion::from_gravity(acc),
+        };
+        self.q = q.cast();
+    }
+
+    pub fn set_quaternion(&mut self, q: Quaternion<N>) {
+        self.q = q;
+    }
+
+    fn base_gain(&mut self, dt: N) -> N {
+        if self.startup_remaining > N::zero() {
+            self.startup_remaining -= dt;
+            self.startup_gain
+        } else {
+            self.gain
+        }
+    }
+
+    fn adapt_gain(&mut self, gain: N, ax: N, ay: N, az: N) {
+        if self.rejection <= N::zero() {
+            self.active_gain = gain;
+            return;
+        }
+        let deviation = ((ax * ax + ay * ay + az * az).sqrt() - self.gravity).abs() / self.gravity;
+        self.active_gain = if deviation >= self.rejection {
+            N::zero()
+        } else {
+            gain * (N::one() - deviation / self.rejection)
+        };
+    }
+
+    pub fn get_quaternion(&self) -> Quaternion<N> {
+        self.q
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            gain: self.gain,
+            active_gain: self.gain,
+            gravity: self.gravity,
+            rejection: self.rejection,
+            zeta: self.zeta,
+            startup_gain: self.startup_gain,
+            startup_time: self.startup_time,
+            startup_remaining: self.startup_time,
+            dt: self.dt,
+            ..Default::default()
+        };
+    }
+
+    pub fn update_imu(&mut self, a_x: N, a_y: N, a_z: N, g_x: N, g_y: N, g_z: N) {
+        self.update_imu_dt(a_x, a_y, a_z, g_x, g_y, g_z, self.dt);
+    }
+
+    /// Same as `update_imu`, integrating over the given elapsed time (sec) instead of 1 / freq.
+    pub fn update_imu_dt(&mut self, a_x: N, a_y: N, a_z: N, g_x: N, g_y: N, g_z: N, dt: N) {
+        let s = self.gravity_step(a_x, a_y, a_z, dt);
+        let g = self.gyro_rate(g_x, g_y, g_z);
+
+        let q_dot = orientation_change_from_gyro(&self.q, g) - s * self.active_gain;
+
+        self.q = self.q + q_dot * dt;
+
+        normalize4d(&mut self.q);
+    }
+
+    /// Propagates the attitude with a gyro sample alone, so that the gyro can be sampled faster
+    /// than the accelerometer correction runs.
+    pub fn update_gyro_dt(&mut self, g_x: N, g_y: N, g_z: N, dt: N) {
+        let g = self.gyro_rate(g_x, g_y, g_z);
+
+        self.q = self.q + orientation_change_from_gyro(&self.q, g) * dt;
+
+        normalize4d(&mut self.q);
+    }
+
+    /// Applies the accelerometer correction alone, `dt` (sec) being the time since the previous
+    /// correction.
+    pub fn correct_imu_dt(&mut self, a_x: N, a_y: N, a_z: N, dt: N) {
+        let s = self.gravity_step(a_x, a_y, a_z, dt);
+
+        self.q = self.q - s * (self.active_gain * dt);
+
+        normalize4d(&mut self.q);
+    }
+
+    /// Same as `correct_imu_dt`, with the magnetometer correcting the heading as well.
+    pub fn correct_marg_dt(&mut self, a_x: N, a_y: N, a_z: N, m_x: N, m_y: N, m_z: N, dt: N) {
+        let zero = N::zero();
+        if (m_x == zero) && (m_y == zero) && (m_z == zero) {
+            self.correct_imu_dt(a_x, a_y, a_z, dt);
+            return;
+        }
+
+        let s = self.marg_step(a_x, a_y, a_z, m_x, m_y, m_z, dt);
+
+        self.q = self.q - s * (self.active_gain * dt);
+
+        normalize4d(&mut self.q);
+    }
+
+    /// Normalized gradient of the gravity and magnetic field errors, zero when the correction
+    /// is skipped.
+    fn marg_step(
+        &mut self,
+        a_x: N,
+        a_y: N,
+        a_z: N,
+        m_x: N,
+        m_y: N,
+        m_z: N,
+        dt: N,
+    ) -> Quaternion<N> {
+        let zero = N::zero();
+        let mut a = [a_x, a_y, a_z];
+        let mut m = [m_x, m_y, m_z];
+        let mut s = Quaternion::new(zero, zero, zero, zero);
+
+        let gain = self.base_gain(dt);
+        let acc_valid = !((a_x == zero) && (a_y == zero) && (a_z == zero));
+        if acc_valid {
+            self.adapt_gain(gain, a_x, a_y, a_z);
+        }
+
+        if acc_valid && self.active_gain > zero {
+            normalize3d(&mut a);
+            normalize3d(&mut m);
+
+            let (_2bxy, _2bz) = compensate_magnetic_distortion(&self.q, m);
+
+            // gravity: [0, 0, 1]
+            add_gradient_descent_step(&self.q, [zero, zero, N::two()], a, &mut s);
+
+            // earth magnetic field: [bxy, 0, bz]
+            add_gradient_descent_step(&self.q, [_2bxy, zero, _2bz], m, &mut s);
+
+            normalize4d(&mut s);
+
+            self.estimate_bias(&s, dt);
+        }
+
+        s
+    }
+
+    /// Normalized gradient of the gravity error, zero when the correction is skipped.
+    fn gravity_step(&mut self, a_x: N, a_y: N, a_z: N, dt: N) -> Quaternion<N> {
+        let zero = N::zero();
+        let mut a = [a_x, a_y, a_z];
+        let mut s = Quaternion::new(zero, zero, zero, zero);
+
+        let gain = self.base_gain(dt);
+        let acc_valid = !((a_x == zero) && (a_y == zero) && (a_z == zero));
+        if acc_valid {
+            self.adapt_gain(gain, a_x, a_y, a_z);
+        }
+
+        if acc_valid && self.active_gain > zero {
+            normalize3d(&mut a);
+
+            add_gradient_descent_step(&self.q, [zero, zero, N::two()], a, &mut s);
+
+            normalize4d(&mut s);
+
+            self.estimate_bias(&s, dt);
+        }
+        s
+    }
+
+    /// Converts to rad/sec and removes the estimated bias.
+    fn gyro_rate(&self, g_x: N, g_y: N, g_z: N) -> [N; 3] {
+        let deg_to_rad = N::deg_to_rad();
+        [
+            g_x * deg_to_rad - self.bias_x,
+            g_y * deg_to_rad - self.bias_y,
+            g_z * deg_to_rad - self.bias_z,
+        ]
+    }
+
+    pub fn update_marg(
+        &mut self,
+        a_x: N,
+        a_y: N,
+        a_z: N,
+        g_x: N,
+        g_y: N,
+        g_z: N,
+        m_x: N,
+        m_y: N,
+        m_z: N,
+    ) {
+        self.update_marg_dt(a_x, a_y, a_z, g_x, g_y, g_z, m_x, m_y, m_z, self.dt);
+    }
+
+    /// Same as `update_marg`, integrating over the given elapsed time (sec) instead of 1 / freq.
+    pub fn update_marg_dt(
+        &mut self,
+        a_x: N,
+        a_y: N,
+        a_z: N,
+        g_x: N,
+        g_y: N,
+        g_z: N,
+        m_x: N,
+        m_y: N,
+        m_z: N,
+        dt: N,
+    ) {
+        let zero = N::zero();
+        if (m_x == zero) && (m_y == zero) && (m_z == zero) {
+            self.update_imu_dt(a_x, a_y, a_z, g_x, g_y, g_z, dt);
+            return;
+        }
+
+        let s = self.marg_step(a_x, a_y, a_z, m_x, m_y, m_z, dt);
+        let g = self.gyro_rate(g_x, g_y, g_z);
+
+        let q_dot = orientation_change_from_gyro(&self.q, g) - s * self.active_gain;
+
+        self.q = self.q + q_dot * dt;
+
+        normalize4d(&mut self.q);
+    }
+}
+
+impl<N: Real> OrientationFilter for Estimated<N> {
+    fn update_imu(&mut self, a_x: f32, a_y: f32, a_z: f32, g_x: f32, g_y: f32, g_z: f32) {
+        let dt = self.dt;
+        Estimated::update_imu_dt(
+            self,
+            N::from_f32(a_x),
+            N::from_f32(a_y),
+            N::from_f32(a_z),
+            N::from_f32(g_x),
+            N::from_f32(g_y),
+            N::from_f32(g_z),
+            dt,
+        )
+    }
+
+    fn update_imu_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        dt: f32,
+    ) {
+        Estimated::update_imu_dt(
+            self,
+            N::from_f32(a_x),
+            N::from_f32(a_y),
+            N::from_f32(a_z),
+            N::from_f32(g_x),
+            N::from_f32(g_y),
+            N::from_f32(g_z),
+            N::from_f32(dt),
+        )
+    }
+
+    fn update_marg(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        m_x: f32,
+        m_y: f32,
+        m_z: f32,
+    ) {
+        let dt = self.dt;
+        Estimated::update_marg_dt(
+            self,
+            N::from_f32(a_x),
+            N::from_f32(a_y),
+            N::from_f32(a_z),
+            N::from_f32(g_x),
+            N::from_f32(g_y),
+            N::from_f32(g_z),
+            N::from_f32(m_x),
+            N::from_f32(m_y),
+            N::from_f32(m_z),
+            dt,
+        )
+    }
+
+    fn update_marg_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        m_x: f32,
+        m_y: f32,
+        m_z: f32,
+        dt: f32,
+    ) {
+        Estimated::update_marg_dt(
+            self,
+            N::from_f32(a_x),
+            N::from_f32(a_y),
+            N::from_f32(a_z),
+            N::from_f32(g_x),
+            N::from_f32(g_y),
+            N::from_f32(g_z),
+            N::from_f32(m_x),
+            N::from_f32(m_y),
+            N::from_f32(m_z),
+            N::from_f32(dt),
+        )
+    }
+
+    fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, dt: f32) {
+        Estimated::update_gyro_dt(
+            self,
+            N::from_f32(g_x),
+            N::from_f32(g_y),
+            N::from_f32(g_z),
+            N::from_f32(dt),
+        )
+    }
+
+    fn correct_imu_dt(&mut self, a_x: f32, a_y: f32, a_z: f32, dt: f32) {
+        Estimated::correct_imu_dt(
+            self,
+            N::from_f32(a_x),
+            N::from_f32(a_y),
+            N::from_f32(a_z),
+            N::from_f32(dt),
+        )
+    }
+
+    fn correct_marg_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        m_x: f32,
+        m_y: f32,
+        m_z: f32,
+        dt: f32,
+    ) {
+        Estimated::correct_marg_dt(
+            self,
+            N::from_f32(a_x),
+            N::from_f32(a_y),
+            N::from_f32(a_z),
+            N::from_f32(m_x),
+            N::from_f32(m_y),
+            N::from_f32(m_z),
+            N::from_f32(dt),
+        )
+    }
+
+    fn get_quaternion(&self) -> Quaternion {
+        self.q.cast()
+    }
+
+    fn set_quaternion(&mut self, q: Quaternion) {
+        self.q = q.cast();
+    }
+
+    fn set_initial(&mut self, acc: [f32; 3], mag: Option<[f32; 3]>) {
+        Estimated::set_initial(self, acc, mag)
+    }
+
+    fn reset(&mut self) {
+        Estimated::reset(self)
+    }
+
+    fn get_gyro_bias(&self) -> [f32; 3] {
+        [
+            self.bias_x.to_f32(),
+            self.bias_y.to_f32(),
+            self.bias_z.to_f32(),
+        ]
+    }
+}
+
+/// Saves the attitude, gain and gyro bias. A restored filter skips the startup phase.
+impl<N: Real> Snapshot for Estimated<N> {
+    const SIZE: usize = 32;
+
+    fn save(&self, w: &mut Writer) {
+        for v in self.q.to_array().iter() {
+            w.f32(v.to_f32());
+        }
+        w.f32(self.gain.to_f32());
+        w.f32(self.bias_x.to_f32());
+        w.f32(self.bias_y.to_f32());
+        w.f32(self.bias_z.to_f32());
+    }
+
+    fn load(&mut self, r: &mut Reader) {
+        self.q = Quaternion::new(r.f32(), r.f32(), r.f32(), r.f32()).cast();
+        self.gain = N::from_f32(r.f32());
+        self.active_gain = self.gain;
+        self.bias_x = N::from_f32(r.f32());
+        self.bias_y = N::from_f32(r.f32());
+        self.bias_z = N::from_f32(r.f32());
+        self.startup_remaining = N::zero();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixed::Q16;
+    use super::super::snapshot;
+    use super::*;
+
+    const FREQ: f32 = 100.0;
+    const MAG_EARTH: [f32; 3] = [0.4, 0.0, -0.9];
+
+    fn similarity(est: &Estimated, q: Quaternion) -> f32 {
+        est.get_quaternion().dot(&q).abs()
+    }
+
+    fn converge(est: &mut Estimated, q: Quaternion, count: u32) {
+        let [ax, ay, az] = q.rotate_inverse([0.0, 0.0, 1.0]);
+        let [mx, my, mz] = q.rotate_inverse(MAG_EARTH);
+        for _ in 0..count {
+            est.update_marg(ax, ay, az, 0.0, 0.0, 0.0, mx, my, mz);
+        }
+    }
+
+    #[test]
+    fn gain_is_constant_without_rejection() {
+        let mut est = Estimated::new(0.1, FREQ);
+        est.update_imu(0.0, 0.0, 3.0, 0.0, 0.0, 0.0);
+        assert_eq!(est.get_gain(), 0.1);
+    }
+
+    #[test]
+    fn gain_scales_with_acceleration_deviation() {
+        let mut est = Estimated::new(0.1, FREQ);
+        est.set_acc_rejection(9.8, 0.5);
+
+        est.update_imu(0.0, 0.0, 9.8, 0.0, 0.0, 0.0);
+        assert!((est.get_gain() - 0.1).abs() < 1e-6);
+
+        est.update_imu(0.0, 0.0, 9.8 * 1.25, 0.0, 0.0, 0.0);
+        assert!((est.get_gain() - 0.05).abs() < 1e-6);
+
+        est.update_imu(0.0, 9.8 * 0.75, 0.0, 0.0, 0.0, 0.0);
+        assert!((est.get_gain() - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn large_acceleration_skips_correction() {
+        let mut est = Estimated::new(0.1, FREQ);
+        est.set_acc_rejection(9.8, 0.5);
+        for _ in 0..100 {
+            est.update_imu(9.8 * 1.5, 0.0, 0.0, 0.0, 0.0, 0.0);
+        }
+        assert_eq!(est.get_gain(), 0.0);
+        let q = est.get_quaternion();
+        assert!(similarity(&est, Quaternion::identity()) > 0.9999);
+        assert_eq!(q.x, 0.0);
+        assert_eq!(q.y, 0.0);
+        assert_eq!(q.z, 0.0);
+
+        est.reset();
+        assert_eq!(est.get_gain(), 0.1);
+        est.update_imu(0.0, 0.0, 9.8 * 2.0, 0.0, 0.0, 0.0);
+        assert_eq!(est.get_gain(), 0.0);
+    }
+
+    #[test]
+    fn startup_gain_expires() {
+        let mut est = Estimated::new(0.1, FREQ);
+        est.set_startup(2.0, 0.5);
+        for _ in 0..45 {
+            est.update_imu(0.0, 0.0, 1.0, 0.0, 0.0, 0.0);
+            assert_eq!(est.get_gain(), 2.0);
+        }
+        for _ in 0..10 {
+            est.update_imu(0.0, 0.0, 1.0, 0.0, 0.0, 0.0);
+        }
+        assert_eq!(est.get_gain(), 0.1);
+
+        est.reset();
+        est.update_imu(0.0, 0.0, 1.0, 0.0, 0.0, 0.0);
+        assert_eq!(est.get_gain(), 2.0);
+    }
+
+    #[test]
+    fn initial_attitude_from_gravity_and_mag() {
+        let truth = Quaternion::from_axis_angle([1.0, -2.0, 3.0], 2.0);
+        let mut est = Estimated::new(0.1, FREQ);
+        est.set_initial(
+            truth.rotate_inverse([0.0, 0.0, 9.8]),
+            Some(truth.rotate_inverse(MAG_EARTH)),
+        );
+        assert!(similarity(&est, truth) > 0.99999);
+    }
+
+    #[test]
+    fn variable_dt_integrates_elapsed_time() {
+        let mut est = Estimated::new(0.1, FREQ);
+        // 90 deg/sec around z over one second split into uneven intervals
+        for dt in [0.1_f32, 0.25, 0.05, 0.3, 0.2, 0.1].iter() {
+            est.update_imu_dt(0.0, 0.0, 0.0, 0.0, 0.0, 90.0, *dt);
+        }
+        let truth = Quaternion::from_axis_angle([0.0, 0.0, 1.0], core::f32::consts::FRAC_PI_2);
+        assert!(similarity(&est, truth) > 0.999);
+    }
+
+    #[test]
+    fn estimates_gyro_bias() {
+        let mut est = Estimated::new(0.1, FREQ);
+        est.set_bias_gain(0.02);
+        for _ in 0..6000 {
+            est.update_imu(0.0, 0.0, 9.8, 1.0, -0.5, 0.0);
+        }
+        let bias = est.get_gyro_bias();
+        assert!((bias[0] - 1.0 * 0.0174533).abs() < 2e-3);
+        assert!((bias[1] + 0.5 * 0.0174533).abs() < 2e-3);
+        assert!(similarity(&est, Quaternion::identity()) > 0.9999);
+
+        est.reset();
+        assert_eq!(est.get_gyro_bias(), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn marg_keeps_identity() {
+        let mut est = Estimated::new(0.1, FREQ);
+        converge(&mut est, Quaternion::identity(), 1000);
+        assert!(similarity(&est, Quaternion::identity()) > 0.9999);
+    }
+
+    #[test]
+    fn marg_corrects_yaw() {
+        let truth = Quaternion::from_axis_angle([0.0, 0.0, 1.0], 1.5);
+
+        let mut imu = Estimated::new(0.5, FREQ);
+        let [ax, ay, az] = truth.rotate_inverse([0.0, 0.0, 1.0]);
+        for _ in 0..3000 {
+            imu.update_imu(ax, ay, az, 0.0, 0.0, 0.0);
+        }
+        assert!(similarity(&imu, truth) < 0.9);
+
+        let mut marg = Estimated::new(0.5, FREQ);
+        converge(&mut marg, truth, 3000);
+        assert!(similarity(&marg, truth) > 0.999);
+    }
+
+    #[test]
+    fn marg_converges_to_arbitrary_rotation() {
+        let truth = Quaternion::from_axis_angle([1.0, -2.0, 3.0], 2.0);
+        let mut est = Estimated::new(0.5, FREQ);
+        converge(&mut est, truth, 5000);
+        assert!(similarity(&est, truth) > 0.999);
+    }
+
+    #[test]
+    fn marg_tracks_rotation() {
+        let mut truth = Quaternion::from_axis_angle([1.0, 1.0, 0.0], 0.5);
+        let mut est = Estimated::new(0.5, FREQ);
+        converge(&mut est, truth, 3000);
+
+        // 45 deg/sec around the sensor's x and z axes for 4 seconds
+        let (gx, gy, gz) = (45.0_f32, 0.0_f32, 45.0_f32);
+        let step =
+            Quaternion::from_rotation_vector([gx * 0.0174533 / FREQ, 0.0, gz * 0.0174533 / FREQ]);
+        for _ in 0..400 {
+            truth = truth * step;
+            let [ax, ay, az] = truth.rotate_inverse([0.0, 0.0, 1.0]);
+            let [mx, my, mz] = truth.rotate_inverse(MAG_EARTH);
+            est.update_marg(ax, ay, az, gx, gy, gz, mx, my, mz);
+        }
+        assert!(similarity(&est, truth) > 0.999);
+    }
+
+    #[test]
+    fn marg_falls_back_to_imu_without_magnetometer() {
+        let truth = Quaternion::from_axis_angle([0.0, 1.0, 0.0], 0.8);
+        let [ax, ay, az] = truth.rotate_inverse([0.0, 0.0, 1.0]);
+
+        let mut imu = Estimated::new(0.1, FREQ);
+        let mut marg = Estimated::new(0.1, FREQ);
+        for _ in 0..100 {
+            imu.update_imu(ax, ay, az, 1.0, -2.0, 3.0);
+            marg.update_marg(ax, ay, az, 1.0, -2.0, 3.0, 0.0, 0.0, 0.0);
+        }
+        assert_eq!(imu.get_quaternion(), marg.get_quaternion());
+    }
+
+    fn run_tilt<N: Real>() -> Quaternion {
+        let mut est = Estimated::<N>::new(N::from_f32(0.1), N::from_f32(FREQ));
+        est.set_bias_gain(N::from_f32(0.005));
+        let q = Quaternion::from_axis_angle([1.0, 0.5, 0.0], 0.6);
+        let [ax, ay, az] = q.rotate_inverse([0.0, 0.0, 9.8]);
+        let [mx, my, mz] = q.rotate_inverse(MAG_EARTH);
+        for _ in 0..2000 {
+            est.update_marg(
+                N::from_f32(ax),
+                N::from_f32(ay),
+                N::from_f32(az),
+                N::from_f32(0.5),
+                N::from_f32(-0.3),
+                N::from_f32(0.2),
+                N::from_f32(mx),
+                N::from_f32(my),
+                N::from_f32(mz),
+            );
+        }
+        est.get_quaternion().cast()
+    }
+
+    #[test]
+    fn double_precision_matches_single() {
+        let single = run_tilt::<f32>();
+        let double = run_tilt::<f64>();
+        assert!(single.dot(&double).abs() > 0.9999);
+    }
+
+    #[test]
+    fn double_precision_level_update_stays_identity() {
+        // an exactly level reading gives a zero gradient
+        let mut est = Estimated::<f64>::new(0.1, FREQ as f64);
+        est.update_imu(0.0, 0.0, 1.0, 0.0, 0.0, 0.0);
+        let q = est.get_quaternion();
+        for v in [q.w, q.x, q.y, q.z].iter() {
+            assert!(v.is_finite());
+        }
+        assert_eq!(q, Quaternion::identity());
+    }
+
+    #[test]
+    fn fixed_point_matches_single() {
+        let single = run_tilt::<f32>();
+        let fixed = run_tilt::<Q16>();
+        assert!((fixed.norm() - 1.0).abs() < 1e-3);
+        assert!(single.dot(&fixed).abs() > 0.999);
+    }
+
+    #[test]
+    fn fixed_point_through_trait() {
+        let mut est: Estimated<Q16> = Estimated::new(Q16::from_f32(0.1), Q16::from_f32(FREQ));
+        let q = Quaternion::from_axis_angle([0.0, 1.0, 0.0], 0.4);
+        let [ax, ay, az] = q.rotate_inverse([0.0, 0.0, 1.0]);
+        for _ in 0..1000 {
+            OrientationFilter::update_imu(&mut est, ax, ay, az, 0.0, 0.0, 0.0);
+        }
+        let result = OrientationFilter::get_quaternion(&est);
+        assert!(result.dot(&q).abs() > 0.999);
+    }
+
+    #[test]
+    fn snapshot_restores_state() {
+        let mut est = Estimated::new(0.1, FREQ);
+        est.set_bias_gain(0.01);
+        for _ in 0..200 {
+            est.update_imu(0.3, -0.2, 9.8, 0.5, -0.4, 0.3);
+        }
+        let mut blob = [0_u8; Estimated::<f32>::SIZE + snapshot::OVERHEAD];
+        snapshot::save(&est, &mut blob).unwrap();
+
+        let mut restored = Estimated::new(0.5, FREQ);
+        restored.set_bias_gain(0.01);
+        restored.set_startup(2.0, 1.0);
+        snapshot::restore(&mut restored, &blob).unwrap();
+        assert_eq!(restored.get_quaternion(), est.get_quaternion());
+        assert_eq!(restored.get_gyro_bias(), est.get_gyro_bias());
+
+        // both carry on identically, the startup gain being skipped
+        est.update_imu(0.3, -0.2, 9.8, 0.5, -0.4, 0.3);
+        restored.update_imu(0.3, -0.2, 9.8, 0.5, -0.4, 0.3);
+        assert_eq!(restored.get_gain(), 0.1);
+        assert_eq!(restored.get_quaternion(), est.get_quaternion());
+    }
+
+    #[test]
+    fn multi_rate_converges_and_tracks() {
+        // gyro at 1 kHz, correction at 100 Hz
+        let truth = Quaternion::from_axis_angle([1.0, 0.5, 0.0], 0.6);
+        let [ax, ay, az] = truth.rotate_inverse([0.0, 0.0, 9.8]);
+        let mut est = Estimated::new(0.1, FREQ);
+        for i in 0..20_000 {
+            est.update_gyro_dt(0.0, 0.0, 0.0, 0.001);
+            if i % 10 == 9 {
+                est.correct_imu_dt(ax, ay, az, 0.01);
+            }
+        }
+        assert!(similarity(&est, truth) > 0.9999);
+
+        // a fast swing around earth z leaves gravity in the body frame untouched
+        let rate = truth.rotate_inverse([0.0, 0.0, 360.0]);
+        for i in 0..250 {
+            est.update_gyro_dt(rate[0], rate[1], rate[2], 0.001);
+            if i % 10 == 9 {
+                est.correct_imu_dt(ax, ay, az, 0.01);
+            }
+        }
+        let swung =
+            Quaternion::from_axis_angle([0.0, 0.0, 1.0], core::f32::consts::FRAC_PI_2) * truth;
+        assert!(similarity(&est, swung) > 0.999);
+    }
+
+    #[test]
+    fn multi_rate_marg_corrects_yaw() {
+        let truth = Quaternion::from_axis_angle([0.2, -0.4, 1.0], 1.2);
+        let [ax, ay, az] = truth.rotate_inverse([0.0, 0.0, 9.8]);
+        let [mx, my, mz] = truth.rotate_inverse(MAG_EARTH);
+        let mut est = Estimated::new(0.1, FREQ);
+        for i in 0..30_000 {
+            est.update_gyro_dt(0.0, 0.0, 0.0, 0.001);
+            if i % 10 == 9 {
+                est.correct_marg_dt(ax, ay, az, mx, my, mz, 0.01);
+            }
+        }
+        assert!(similarity(&est, truth) > 0.9999);
+    }
+}
@@ -0,0 +1,267 @@
+use super::filter::OrientationFilter;
+use super::quaternion::Quaternion;
+use super::real::{Real, DEG_TO_RAD};
+
+#[derive(Clone, Copy)]
+pub struct Mahony {
+    kp: f32,
+    ki: f32,
+    q: Quaternion,
+    integral_x: f32,
+    integral_y: f32,
+    integral_z: f32,
+    dt: f32,
+}
+
+impl Default for Mahony {
+    fn default() -> Self {
+        Self {
+            kp: 1.0,
+            ki: 0.0,
+            q: Quaternion::identity(),
+            integral_x: 0.0,
+            integral_y: 0.0,
+            integral_z: 0.0,
+            dt: 1.0 / 512.0,
+        }
+    }
+}
+
+impl Mahony {
+    pub fn new(kp: f32, ki: f32, freq: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            dt: 1.0_f32 / freq,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    pub fn set_quaternion(&mut self, q: Quaternion) {
+        self.q = q;
+    }
+
+    /// Returns the gyro bias (rad/sec) cancelled by the integral feedback.
+    pub fn get_gyro_bias(&self) -> [f32; 3] {
+        [-self.integral_x, -self.integral_y, -self.integral_z]
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            kp: self.kp,
+            ki: self.ki,
+            dt: self.dt,
+            ..Default::default()
+        };
+    }
+
+    pub fn update_imu(&mut self, a_x: f32, a_y: f32, a_z: f32, g_x: f32, g_y: f32, g_z: f32) {
+        self.update_imu_dt(a_x, a_y, a_z, g_x, g_y, g_z, self.dt);
+    }
+
+    /// Same as `update_imu`, integrating over the given elapsed time (sec) instead of 1 / freq.
+    pub fn update_imu_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        dt: f32,
+    ) {
+        // convert to rad/sec, the integral feedback included whether or not the accelerometer
+        // reading is usable, as in `update_gyro_dt`
+        let mut gx = g_x * DEG_TO_RAD;
+        let mut gy = g_y * DEG_TO_RAD;
+        let mut gz = g_z * DEG_TO_RAD;
+
+        if let Some([ex, ey, ez]) = self.gravity_error(a_x, a_y, a_z) {
+            self.integrate_error(ex, ey, ez, dt);
+            gx += self.kp * ex;
+            gy += self.kp * ey;
+            gz += self.kp * ez;
+        }
+
+        self.rotate(
+            gx + self.integral_x,
+            gy + self.integral_y,
+            gz + self.integral_z,
+            dt,
+        );
+    }
+
+    /// Propagates the attitude with a gyro sample alone, the integral feedback included.
+    pub fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, dt: f32) {
+        self.rotate(
+            g_x * DEG_TO_RAD + self.integral_x,
+            g_y * DEG_TO_RAD + self.integral_y,
+            g_z * DEG_TO_RAD + self.integral_z,
+            dt,
+        );
+    }
+
+    /// Applies the accelerometer feedback alone, `dt` (sec) being the time since the previous
+    /// correction.
+    pub fn correct_imu_dt(&mut self, a_x: f32, a_y: f32, a_z: f32, dt: f32) {
+        if let Some([ex, ey, ez]) = self.gravity_error(a_x, a_y, a_z) {
+            self.integrate_error(ex, ey, ez, dt);
+            self.rotate(self.kp * ex, self.kp * ey, self.kp * ez, dt);
+        }
+    }
+
+    /// Cross product between the measured and the estimated direction of gravity.
+    fn gravity_error(&self, a_x: f32, a_y: f32, a_z: f32) -> Option<[f32; 3]> {
+        if (a_x == 0.0_f32) && (a_y == 0.0_f32) && (a_z == 0.0_f32) {
+            return None;
+        }
+        let norm = (a_x * a_x + a_y * a_y + a_z * a_z).inv_sqrt();
+        let ax = a_x * norm;
+        let ay = a_y * norm;
+        let az = a_z * norm;
+
+        // estimated direction of gravity
+        let [vx, vy, vz] = self.q.rotate_inverse([0.0, 0.0, 1.0]);
+
+        Some([ay * vz - az * vy, az * vx - ax * vz, ax * vy - ay * vx])
+    }
+
+    fn integrate_error(&mut self, ex: f32, ey: f32, ez: f32, dt: f32) {
+        if self.ki > 0.0_f32 {
+            self.integral_x += self.ki * ex * dt;
+            self.integral_y += self.ki * ey * dt;
+            self.integral_z += self.ki * ez * dt;
+        } else {
+            self.integral_x = 0.0;
+            self.integral_y = 0.0;
+            self.integral_z = 0.0;
+        }
+    }
+
+    fn rotate(&mut self, gx: f32, gy: f32, gz: f32, dt: f32) {
+        self.q = self.q + self.q * Quaternion::new(0.0, gx, gy, gz) * (0.5_f32 * dt);
+        self.q = self.q.normalize();
+    }
+}
+
+impl OrientationFilter for Mahony {
+    fn update_imu(&mut self, a_x: f32, a_y: f32, a_z: f32, g_x: f32, g_y: f32, g_z: f32) {
+        Mahony::update_imu(self, a_x, a_y, a_z, g_x, g_y, g_z)
+    }
+
+    fn update_imu_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        dt: f32,
+    ) {
+        Mahony::update_imu_dt(self, a_x, a_y, a_z, g_x, g_y, g_z, dt)
+    }
+
+    fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, dt: f32) {
+        Mahony::update_gyro_dt(self, g_x, g_y, g_z, dt)
+    }
+
+    fn correct_imu_dt(&mut self, a_x: f32, a_y: f32, a_z: f32, dt: f32) {
+        Mahony::correct_imu_dt(self, a_x, a_y, a_z, dt)
+    }
+
+    fn get_quaternion(&self) -> Quaternion {
+        Mahony::get_quaternion(self)
+    }
+
+    fn set_quaternion(&mut self, q: Quaternion) {
+        Mahony::set_quaternion(self, q)
+    }
+
+    fn reset(&mut self) {
+        Mahony::reset(self)
+    }
+
+    fn get_gyro_bias(&self) -> [f32; 3] {
+        Mahony::get_gyro_bias(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::{cosf, sinf};
+
+    #[test]
+    fn converges_to_tilt() {
+        // 0.6 rad around x: gravity seen as (0, sin, cos)
+        let mut est = Mahony::new(2.0, 0.0, 100.0);
+        for _ in 0..2000 {
+            est.update_imu(0.0, sinf(0.6), cosf(0.6), 0.0, 0.0, 0.0);
+        }
+        let q = est.get_quaternion();
+        assert!((q.w - cosf(0.3)).abs() < 1e-3);
+        assert!((q.x - sinf(0.3)).abs() < 1e-3);
+        assert!(q.y.abs() < 1e-3);
+        assert!(q.z.abs() < 1e-3);
+    }
+
+    #[test]
+    fn integral_term_cancels_gyro_offset() {
+        let mut est = Mahony::new(1.0, 0.5, 100.0);
+        for _ in 0..10000 {
+            est.update_imu(0.0, 0.0, 9.8, 2.0, -1.0, 0.0);
+        }
+        let q = est.get_quaternion();
+        assert!(q.x.abs() < 1e-3);
+        assert!(q.y.abs() < 1e-3);
+        assert!((est.integral_x + 2.0 * DEG_TO_RAD).abs() < 1e-3);
+        assert!((est.integral_y - 1.0 * DEG_TO_RAD).abs() < 1e-3);
+    }
+
+    #[test]
+    fn integral_applies_without_accelerometer() {
+        let mut est = Mahony::new(1.0, 0.5, 100.0);
+        for _ in 0..10000 {
+            est.update_imu(0.0, 0.0, 9.8, 2.0, -1.0, 0.0);
+        }
+        // an unusable reading propagates like the gyro alone, the learned offset still removed
+        let mut gyro_only = est;
+        est.update_imu_dt(0.0, 0.0, 0.0, 2.0, -1.0, 0.0, 0.01);
+        gyro_only.update_gyro_dt(2.0, -1.0, 0.0, 0.01);
+        assert_eq!(est.get_quaternion(), gyro_only.get_quaternion());
+        assert!(est.get_quaternion().x.abs() < 1e-3);
+    }
+
+    #[test]
+    fn reset_keeps_gains() {
+        let mut est = Mahony::new(2.0, 0.1, 50.0);
+        est.update_imu(0.0, 1.0, 0.0, 10.0, 0.0, 0.0);
+        est.reset();
+        assert_eq!(est.get_quaternion(), Quaternion::identity());
+        assert_eq!(est.integral_x, 0.0);
+        assert_eq!(est.kp, 2.0);
+        assert_eq!(est.ki, 0.1);
+        assert_eq!(est.dt, 1.0 / 50.0);
+    }
+
+    #[test]
+    fn multi_rate_cancels_gyro_offset() {
+        // gyro at 1 kHz, correction at 100 Hz
+        let mut est = Mahony::new(1.0, 0.5, 100.0);
+        for i in 0..100_000 {
+            est.update_gyro_dt(2.0, -1.0, 0.0, 0.001);
+            if i % 10 == 9 {
+                est.correct_imu_dt(0.0, 0.0, 9.8, 0.01);
+            }
+        }
+        let q = est.get_quaternion();
+        assert!(q.x.abs() < 1e-3);
+        assert!(q.y.abs() < 1e-3);
+        assert!((est.integral_x + 2.0 * DEG_TO_RAD).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,51 @@
+//! Small fixed-size matrix helpers shared by the filters and the calibrations.
+
+pub(crate) type Mat3 = [[f32; 3]; 3];
+
+/// Inverse by cofactors, `None` for a singular matrix.
+pub(crate) fn invert3(m: &Mat3) -> Option<Mat3> {
+    let c00 = m[1][1] * m[2][2] - m[1][2] * m[2][1];
+    let c01 = m[1][2] * m[2][0] - m[1][0] * m[2][2];
+    let c02 = m[1][0] * m[2][1] - m[1][1] * m[2][0];
+    let det = m[0][0] * c00 + m[0][1] * c01 + m[0][2] * c02;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv = 1.0 / det;
+    Some([
+        [
+            c00 * inv,
+            (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv,
+            (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv,
+        ],
+        [
+            c01 * inv,
+            (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv,
+            (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv,
+        ],
+        [
+            c02 * inv,
+            (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv,
+            (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv,
+        ],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_times_matrix_is_identity() {
+        let m = [[2.0, 0.5, 0.0], [0.1, 1.0, -0.3], [0.0, 0.2, 4.0]];
+        let inv = invert3(&m).unwrap();
+        for (i, row) in inv.iter().enumerate() {
+            for j in 0..3 {
+                let p: f32 = row.iter().zip(m.iter()).map(|(a, r)| a * r[j]).sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((p - expected).abs() < 1e-6);
+            }
+        }
+        assert!(invert3(&[[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 0.0, 1.0]]).is_none());
+    }
+}
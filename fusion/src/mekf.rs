@@ -0,0 +1,362 @@
+#![allow(clippy::needless_range_loop)]
+
+use libm::sqrtf;
+
+use super::filter::OrientationFilter;
+use super::matrix::{invert3, Mat3};
+use super::quaternion::Quaternion;
+
+// initial standard deviations of attitude (rad) and gyro bias (rad/sec)
+const INIT_SIGMA_ATTITUDE: f32 = 0.5;
+const INIT_SIGMA_BIAS: f32 = 0.02;
+
+type Mat6 = [[f32; 6]; 6];
+
+fn skew(x: f32, y: f32, z: f32) -> Mat3 {
+    [[0.0, -z, y], [z, 0.0, -x], [-y, x, 0.0]]
+}
+
+/// Multiplicative extended Kalman filter.
+///
+/// The error state is the attitude error (rad, body frame) followed by the gyro bias (rad/sec),
+/// and the accelerometer is used as a measurement of the gravity direction.
+#[derive(Clone, Copy)]
+pub struct Mekf {
+    q: Quaternion,
+    bias_x: f32,
+    bias_y: f32,
+    bias_z: f32,
+    p: Mat6,
+    gyro_noise: f32,
+    bias_noise: f32,
+    acc_noise: f32,
+    dt: f32,
+}
+
+impl Default for Mekf {
+    fn default() -> Self {
+        let mut mekf = Self {
+            q: Quaternion::identity(),
+            bias_x: 0.0,
+            bias_y: 0.0,
+            bias_z: 0.0,
+            p: [[0.0; 6]; 6],
+            gyro_noise: 0.01,
+            bias_noise: 0.0001,
+            acc_noise: 0.05,
+            dt: 1.0 / 512.0,
+        };
+        mekf.reset();
+        mekf
+    }
+}
+
+impl Mekf {
+    /// `gyro_noise` (rad/sec), `bias_noise` (rad/sec per sqrt(sec)) and `acc_noise`
+    /// (fraction of gravity) are standard deviations.
+    pub fn new(freq: f32, gyro_noise: f32, bias_noise: f32, acc_noise: f32) -> Self {
+        Self {
+            gyro_noise,
+            bias_noise,
+            acc_noise,
+            dt: 1.0_f32 / freq,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    pub fn set_quaternion(&mut self, q: Quaternion) {
+        self.q = q;
+    }
+
+    /// Variances of the attitude error around x, y and z (rad^2).
+    pub fn get_covariance_diag(&self) -> [f32; 3] {
+        [self.p[0][0], self.p[1][1], self.p[2][2]]
+    }
+
+    /// Estimated gyro bias (rad/sec).
+    pub fn get_gyro_bias(&self) -> [f32; 3] {
+        [self.bias_x, self.bias_y, self.bias_z]
+    }
+
+    pub fn reset(&mut self) {
+        self.q = Quaternion::identity();
+        self.bias_x = 0.0;
+        self.bias_y = 0.0;
+        self.bias_z = 0.0;
+        self.p = [[0.0; 6]; 6];
+        for i in 0..3 {
+            self.p[i][i] = INIT_SIGMA_ATTITUDE * INIT_SIGMA_ATTITUDE;
+            self.p[i + 3][i + 3] = INIT_SIGMA_BIAS * INIT_SIGMA_BIAS;
+        }
+    }
+
+    fn predict(&mut self, gx: f32, gy: f32, gz: f32, dt: f32) {
+        let wx = gx - self.bias_x;
+        let wy = gy - self.bias_y;
+        let wz = gz - self.bias_z;
+        self.q =
+            (self.q * Quaternion::from_rotation_vector([wx * dt, wy * dt, wz * dt])).normalize();
+
+        // F = [[I - [w]x dt, -I dt], [0, I]]
+        let w = skew(wx, wy, wz);
+        let mut f: Mat6 = [[0.0; 6]; 6];
+        for i in 0..6 {
+            f[i][i] = 1.0;
+        }
+        for i in 0..3 {
+            for j in 0..3 {
+                f[i][j] -= w[i][j] * dt;
+            }
+            f[i][i + 3] = -dt;
+        }
+
+        // P <- F P F^T + Q
+        let mut fp: Mat6 = [[0.0; 6]; 6];
+        for i in 0..6 {
+            for j in 0..6 {
+                let mut sum = 0.0;
+                for k in 0..6 {
+                    sum += f[i][k] * self.p[k][j];
+                }
+                fp[i][j] = sum;
+            }
+        }
+        for i in 0..6 {
+            for j in i..6 {
+                let mut sum = 0.0;
+                for k in 0..6 {
+                    sum += fp[i][k] * f[j][k];
+                }
+                self.p[i][j] = sum;
+                self.p[j][i] = sum;
+            }
+        }
+        let q_gyro = self.gyro_noise * self.gyro_noise * dt;
+        let q_bias = self.bias_noise * self.bias_noise * dt;
+        for i in 0..3 {
+            self.p[i][i] += q_gyro;
+            self.p[i + 3][i + 3] += q_bias;
+        }
+    }
+
+    fn correct(&mut self, ax: f32, ay: f32, az: f32) {
+        // predicted direction of gravity in the body frame
+        let [hx, hy, hz] = self.q.rotate_inverse([0.0, 0.0, 1.0]);
+
+        // H = [[h]x, 0]
+        let h = skew(hx, hy, hz);
+
+        // P H^T (6x3), only the attitude columns of P contribute
+        let mut pht = [[0.0_f32; 3]; 6];
+        for i in 0..6 {
+            for j in 0..3 {
+                let mut sum = 0.0;
+                for k in 0..3 {
+                    sum += self.p[i][k] * h[j][k];
+                }
+                pht[i][j] = sum;
+            }
+        }
+
+        // S = H P H^T + R
+        let mut s: Mat3 = [[0.0; 3]; 3];
+        let r = self.acc_noise * self.acc_noise;
+        for i in 0..3 {
+            for j in 0..3 {
+                let mut sum = 0.0;
+                for k in 0..3 {
+                    sum += h[i][k] * pht[k][j];
+                }
+                s[i][j] = sum;
+            }
+            s[i][i] += r;
+        }
+        let s_inv = match invert3(&s) {
+            Some(inv) => inv,
+            None => return,
+        };
+
+        // K = P H^T S^-1
+        let mut k: [[f32; 3]; 6] = [[0.0; 3]; 6];
+        for i in 0..6 {
+            for j in 0..3 {
+                let mut sum = 0.0;
+                for l in 0..3 {
+                    sum += pht[i][l] * s_inv[l][j];
+                }
+                k[i][j] = sum;
+            }
+        }
+
+        let innovation = [ax - hx, ay - hy, az - hz];
+        let mut dx = [0.0_f32; 6];
+        for i in 0..6 {
+            for j in 0..3 {
+                dx[i] += k[i][j] * innovation[j];
+            }
+        }
+
+        // P <- P - K (P H^T)^T
+        for i in 0..6 {
+            for j in i..6 {
+                let mut sum = 0.0;
+                for l in 0..3 {
+                    sum += k[i][l] * pht[j][l];
+                }
+                self.p[i][j] -= sum;
+                self.p[j][i] = self.p[i][j];
+            }
+        }
+
+        self.q = (self.q * Quaternion::from_rotation_vector([dx[0], dx[1], dx[2]])).normalize();
+        self.bias_x += dx[3];
+        self.bias_y += dx[4];
+        self.bias_z += dx[5];
+    }
+
+    pub fn update_imu(&mut self, a_x: f32, a_y: f32, a_z: f32, g_x: f32, g_y: f32, g_z: f32) {
+        self.update_imu_dt(a_x, a_y, a_z, g_x, g_y, g_z, self.dt);
+    }
+
+    /// Same as `update_imu`, integrating over the given elapsed time (sec) instead of 1 / freq.
+    pub fn update_imu_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        dt: f32,
+    ) {
+        self.update_gyro_dt(g_x, g_y, g_z, dt);
+        self.correct_imu(a_x, a_y, a_z);
+    }
+
+    /// Runs the prediction alone, for sampling the gyro faster than the accelerometer.
+    pub fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, dt: f32) {
+        // convert to rad/sec
+        let gx = g_x * 0.0174533_f32;
+        let gy = g_y * 0.0174533_f32;
+        let gz = g_z * 0.0174533_f32;
+
+        self.predict(gx, gy, gz, dt);
+    }
+
+    /// Runs the gravity measurement update alone. The elapsed time is already accounted for by
+    /// the predictions.
+    pub fn correct_imu(&mut self, a_x: f32, a_y: f32, a_z: f32) {
+        if !((a_x == 0.0_f32) && (a_y == 0.0_f32) && (a_z == 0.0_f32)) {
+            let norm = 1.0 / sqrtf(a_x * a_x + a_y * a_y + a_z * a_z);
+            self.correct(a_x * norm, a_y * norm, a_z * norm);
+        }
+    }
+}
+
+impl OrientationFilter for Mekf {
+    fn update_imu(&mut self, a_x: f32, a_y: f32, a_z: f32, g_x: f32, g_y: f32, g_z: f32) {
+        Mekf::update_imu(self, a_x, a_y, a_z, g_x, g_y, g_z)
+    }
+
+    fn update_imu_dt(
+        &mut self,
+        a_x: f32,
+        a_y: f32,
+        a_z: f32,
+        g_x: f32,
+        g_y: f32,
+        g_z: f32,
+        dt: f32,
+    ) {
+        Mekf::update_imu_dt(self, a_x, a_y, a_z, g_x, g_y, g_z, dt)
+    }
+
+    fn update_gyro_dt(&mut self, g_x: f32, g_y: f32, g_z: f32, dt: f32) {
+        Mekf::update_gyro_dt(self, g_x, g_y, g_z, dt)
+    }
+
+    fn correct_imu_dt(&mut self, a_x: f32, a_y: f32, a_z: f32, _dt: f32) {
+        Mekf::correct_imu(self, a_x, a_y, a_z)
+    }
+
+    fn get_quaternion(&self) -> Quaternion {
+        Mekf::get_quaternion(self)
+    }
+
+    fn set_quaternion(&mut self, q: Quaternion) {
+        Mekf::set_quaternion(self, q)
+    }
+
+    fn reset(&mut self) {
+        Mekf::reset(self)
+    }
+
+    fn get_gyro_bias(&self) -> [f32; 3] {
+        Mekf::get_gyro_bias(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libm::{cosf, sinf};
+
+    #[test]
+    fn converges_to_tilt() {
+        let mut est = Mekf::new(100.0, 0.01, 0.0001, 0.05);
+        for _ in 0..500 {
+            est.update_imu(0.0, sinf(0.6), cosf(0.6), 0.0, 0.0, 0.0);
+        }
+        let q = est.get_quaternion();
+        assert!((q.w - cosf(0.3)).abs() < 1e-3);
+        assert!((q.x - sinf(0.3)).abs() < 1e-3);
+        assert!(q.y.abs() < 1e-3);
+        assert!(q.z.abs() < 1e-3);
+    }
+
+    #[test]
+    fn covariance_shrinks_only_for_observable_axes() {
+        let mut est = Mekf::new(100.0, 0.01, 0.0001, 0.05);
+        let init = est.get_covariance_diag();
+        for _ in 0..500 {
+            est.update_imu(0.0, 0.0, 1.0, 0.0, 0.0, 0.0);
+        }
+        let diag = est.get_covariance_diag();
+        assert!(diag[0] < init[0] * 0.01);
+        assert!(diag[1] < init[1] * 0.01);
+        // yaw is not observable from gravity
+        assert!(diag[2] >= init[2]);
+    }
+
+    #[test]
+    fn estimates_gyro_bias() {
+        let mut est = Mekf::new(100.0, 0.01, 0.0001, 0.05);
+        for _ in 0..6000 {
+            est.update_imu(0.0, 0.0, 9.8, 0.5, -0.3, 0.0);
+        }
+        let bias = est.get_gyro_bias();
+        assert!((bias[0] - 0.5 * 0.0174533).abs() < 1e-3);
+        assert!((bias[1] + 0.3 * 0.0174533).abs() < 1e-3);
+        let q = est.get_quaternion();
+        assert!(q.x.abs() < 1e-2);
+        assert!(q.y.abs() < 1e-2);
+    }
+
+    #[test]
+    fn multi_rate_converges_to_tilt() {
+        let truth = Quaternion::from_axis_angle([0.0, 1.0, 0.0], 0.5);
+        let [ax, ay, az] = truth.rotate_inverse([0.0, 0.0, 1.0]);
+        let mut est = Mekf::new(100.0, 0.01, 0.0001, 0.05);
+        for i in 0..10_000 {
+            est.update_gyro_dt(0.0, 0.0, 0.0, 0.001);
+            if i % 10 == 9 {
+                est.correct_imu(ax, ay, az);
+            }
+        }
+        assert!(est.get_quaternion().dot(&truth).abs() > 0.9999);
+    }
+}
@@ -0,0 +1,50 @@
+use super::quaternion::Quaternion;
+
+/// Direction of gravity (pointing up, as the accelerometer sees it at rest) in the body frame.
+pub fn gravity_in_body(q: &Quaternion, gravity: f32) -> [f32; 3] {
+    q.rotate_inverse([0.0, 0.0, gravity])
+}
+
+/// Accelerometer reading with gravity removed, in the body frame.
+pub fn linear_acceleration(q: &Quaternion, acc: [f32; 3], gravity: f32) -> [f32; 3] {
+    let g = gravity_in_body(q, gravity);
+    [acc[0] - g[0], acc[1] - g[1], acc[2] - g[2]]
+}
+
+/// Accelerometer reading with gravity removed, in the earth frame (z up).
+pub fn earth_linear_acceleration(q: &Quaternion, acc: [f32; 3], gravity: f32) -> [f32; 3] {
+    let a = q.rotate(acc);
+    [a[0], a[1], a[2] - gravity]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: f32 = 9.80665;
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn gravity_is_removed_at_rest() {
+        // 0.6 rad around x
+        let q = Quaternion::from_axis_angle([1.0, 0.0, 0.0], 0.6);
+        let acc = [0.0, G * libm::sinf(0.6), G * libm::cosf(0.6)];
+        assert_close(gravity_in_body(&q, G), acc);
+        assert_close(linear_acceleration(&q, acc, G), [0.0, 0.0, 0.0]);
+        assert_close(earth_linear_acceleration(&q, acc, G), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn earth_frame_follows_heading() {
+        // facing 90 deg to the left, accelerating along the body x axis
+        let q = Quaternion::from_axis_angle([0.0, 0.0, 1.0], core::f32::consts::FRAC_PI_2);
+        let acc = [1.5, 0.0, G];
+        assert_close(linear_acceleration(&q, acc, G), [1.5, 0.0, 0.0]);
+        assert_close(earth_linear_acceleration(&q, acc, G), [0.0, 1.5, 0.0]);
+    }
+}
@@ -0,0 +1,409 @@
+use core::ops::{Add, Mul, Neg, Sub};
+
+use libm::{acosf, asinf, atan2f, cosf, sinf, sqrtf};
+
+use super::real::Real;
+
+/// Unit quaternion rotating body frame vectors into the earth frame, scalar first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion<N = f32> {
+    pub w: N,
+    pub x: N,
+    pub y: N,
+    pub z: N,
+}
+
+impl<N: Real> Default for Quaternion<N> {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl<N> Quaternion<N> {
+    pub const fn new(w: N, x: N, y: N, z: N) -> Self {
+        Self { w, x, y, z }
+    }
+}
+
+impl<N: Real> Quaternion<N> {
+    pub fn identity() -> Self {
+        Self::new(N::one(), N::zero(), N::zero(), N::zero())
+    }
+
+    pub fn from_array(q: [N; 4]) -> Self {
+        Self::new(q[0], q[1], q[2], q[3])
+    }
+
+    pub fn to_array(&self) -> [N; 4] {
+        [self.w, self.x, self.y, self.z]
+    }
+
+    /// Converts to another numeric type, going through f32.
+    pub fn cast<M: Real>(&self) -> Quaternion<M> {
+        Quaternion::new(
+            M::from_f32(self.w.to_f32()),
+            M::from_f32(self.x.to_f32()),
+            M::from_f32(self.y.to_f32()),
+            M::from_f32(self.z.to_f32()),
+        )
+    }
+
+    pub fn dot(&self, other: &Self) -> N {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn norm(&self) -> N {
+        self.dot(self).sqrt()
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Returns the quaternion scaled to unit length, or itself if its length is zero.
+    pub fn normalize(&self) -> Self {
+        let norm = self.norm();
+        if norm == N::zero() {
+            return *self;
+        }
+        *self * (N::one() / norm)
+    }
+
+    /// Rotates a body frame vector into the earth frame.
+    pub fn rotate(&self, v: [N; 3]) -> [N; 3] {
+        let m = self.to_rotation_matrix();
+        [
+            m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+            m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+            m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+        ]
+    }
+
+    /// Rotates an earth frame vector into the body frame.
+    pub fn rotate_inverse(&self, v: [N; 3]) -> [N; 3] {
+        self.conjugate().rotate(v)
+    }
+
+    pub fn to_rotation_matrix(&self) -> [[N; 3]; 3] {
+        let Self { w, x, y, z } = *self;
+        let one = N::one();
+        let two = one + one;
+        [
+            [
+                one - two * (y * y + z * z),
+                two * (x * y - w * z),
+                two * (x * z + w * y),
+            ],
+            [
+                two * (x * y + w * z),
+                one - two * (x * x + z * z),
+                two * (y * z - w * x),
+            ],
+            [
+                two * (x * z - w * y),
+                two * (y * z + w * x),
+                one - two * (x * x + y * y),
+            ],
+        ]
+    }
+}
+
+impl Quaternion<f32> {
+    /// Spherical linear interpolation along the shortest arc, `t` in [0, 1].
+    pub fn slerp(&self, other: &Self, t: f32) -> Self {
+        let mut cos_theta = self.dot(other);
+        let mut end = *other;
+        if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            end = -end;
+        }
+        if cos_theta > 0.9995 {
+            return (*self * (1.0 - t) + end * t).normalize();
+        }
+        let theta = acosf(cos_theta);
+        let sin_theta = sinf(theta);
+        let a = sinf((1.0 - t) * theta) / sin_theta;
+        let b = sinf(t * theta) / sin_theta;
+        *self * a + end * b
+    }
+
+    pub fn from_rotation_matrix(m: &[[f32; 3]; 3]) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0.0 {
+            let s = 2.0 * sqrtf(trace + 1.0);
+            Self::new(
+                0.25 * s,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * sqrtf(1.0 + m[0][0] - m[1][1] - m[2][2]);
+            Self::new(
+                (m[2][1] - m[1][2]) / s,
+                0.25 * s,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * sqrtf(1.0 + m[1][1] - m[0][0] - m[2][2]);
+            Self::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                0.25 * s,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = 2.0 * sqrtf(1.0 + m[2][2] - m[0][0] - m[1][1]);
+            Self::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                0.25 * s,
+            )
+        };
+        q.normalize()
+    }
+
+    /// Rotation of `angle` (rad) around `axis`, which need not be normalized.
+    pub fn from_axis_angle(axis: [f32; 3], angle: f32) -> Self {
+        let norm = sqrtf(axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]);
+        if norm == 0.0 {
+            return Self::identity();
+        }
+        let s = sinf(angle * 0.5) / norm;
+        Self::new(cosf(angle * 0.5), axis[0] * s, axis[1] * s, axis[2] * s)
+    }
+
+    /// Returns the unit rotation axis and the angle (rad) in [0, 2 pi).
+    pub fn to_axis_angle(&self) -> ([f32; 3], f32) {
+        let q = self.normalize();
+        let s = sqrtf(q.x * q.x + q.y * q.y + q.z * q.z);
+        if s < 1e-6 {
+            return ([1.0, 0.0, 0.0], 0.0);
+        }
+        ([q.x / s, q.y / s, q.z / s], 2.0 * atan2f(s, q.w))
+    }
+
+    /// Rotation by the vector `r`, whose length is the angle (rad).
+    pub fn from_rotation_vector(r: [f32; 3]) -> Self {
+        let angle = sqrtf(r[0] * r[0] + r[1] * r[1] + r[2] * r[2]);
+        if angle < 1e-6 {
+            return Self::new(1.0, r[0] * 0.5, r[1] * 0.5, r[2] * 0.5).normalize();
+        }
+        Self::from_axis_angle(r, angle)
+    }
+
+    /// Level attitude with zero yaw whose body frame sees the accelerometer reading `acc`.
+    pub fn from_gravity(acc: [f32; 3]) -> Self {
+        let roll = atan2f(acc[1], acc[2]);
+        let pitch = atan2f(-acc[0], sqrtf(acc[1] * acc[1] + acc[2] * acc[2]));
+        Self::from_euler_zyx(roll, pitch, 0.0)
+    }
+
+    /// Attitude from gravity whose heading puts the horizontal part of `mag` along earth x.
+    pub fn from_gravity_and_mag(acc: [f32; 3], mag: [f32; 3]) -> Self {
+        let tilt = Self::from_gravity(acc);
+        let h = tilt.rotate(mag);
+        Self::from_axis_angle([0.0, 0.0, 1.0], -atan2f(h[1], h[0])) * tilt
+    }
+
+    /// Intrinsic z-y'-x'' rotation, i.e. yaw, then pitch, then roll.
+    pub fn from_euler_zyx(roll: f32, pitch: f32, yaw: f32) -> Self {
+        Self::from_axis_angle([0.0, 0.0, 1.0], yaw)
+            * Self::from_axis_angle([0.0, 1.0, 0.0], pitch)
+            * Self::from_axis_angle([1.0, 0.0, 0.0], roll)
+    }
+
+    /// Returns (roll, pitch, yaw) of the intrinsic z-y'-x'' rotation.
+    pub fn to_euler_zyx(&self) -> (f32, f32, f32) {
+        let m = self.to_rotation_matrix();
+        let roll = atan2f(m[2][1], m[2][2]);
+        let pitch = asinf((-m[2][0]).clamp(-1.0, 1.0));
+        let yaw = atan2f(m[1][0], m[0][0]);
+        (roll, pitch, yaw)
+    }
+
+    /// Intrinsic x-y'-z'' rotation.
+    pub fn from_euler_xyz(x: f32, y: f32, z: f32) -> Self {
+        Self::from_axis_angle([1.0, 0.0, 0.0], x)
+            * Self::from_axis_angle([0.0, 1.0, 0.0], y)
+            * Self::from_axis_angle([0.0, 0.0, 1.0], z)
+    }
+
+    /// Returns the angles (x, y, z) of the intrinsic x-y'-z'' rotation.
+    pub fn to_euler_xyz(&self) -> (f32, f32, f32) {
+        let m = self.to_rotation_matrix();
+        let x = atan2f(-m[1][2], m[2][2]);
+        let y = asinf(m[0][2].clamp(-1.0, 1.0));
+        let z = atan2f(-m[0][1], m[0][0]);
+        (x, y, z)
+    }
+}
+
+impl<N: Real> Mul for Quaternion<N> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        )
+    }
+}
+
+impl<N: Real> Mul<N> for Quaternion<N> {
+    type Output = Self;
+
+    fn mul(self, rhs: N) -> Self {
+        Self::new(self.w * rhs, self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl<N: Real> Add for Quaternion<N> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(
+            self.w + rhs.w,
+            self.x + rhs.x,
+            self.y + rhs.y,
+            self.z + rhs.z,
+        )
+    }
+}
+
+impl<N: Real> Sub for Quaternion<N> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(
+            self.w - rhs.w,
+            self.x - rhs.x,
+            self.y - rhs.y,
+            self.z - rhs.z,
+        )
+    }
+}
+
+impl<N: Real> Neg for Quaternion<N> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.w, -self.x, -self.y, -self.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same_rotation(a: Quaternion, b: Quaternion) {
+        assert!(a.dot(&b).abs() > 0.99999, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn multiply_composes_rotations() {
+        let a = Quaternion::from_axis_angle([0.0, 0.0, 1.0], 0.5);
+        let b = Quaternion::from_axis_angle([0.0, 0.0, 1.0], 0.7);
+        assert_same_rotation(a * b, Quaternion::from_axis_angle([0.0, 0.0, 1.0], 1.2));
+        assert_same_rotation(a * a.conjugate(), Quaternion::identity());
+    }
+
+    #[test]
+    fn rotates_vectors() {
+        let q = Quaternion::from_axis_angle([0.0, 0.0, 1.0], core::f32::consts::FRAC_PI_2);
+        assert_close(q.rotate([1.0, 0.0, 0.0]), [0.0, 1.0, 0.0]);
+        assert_close(q.rotate_inverse([0.0, 1.0, 0.0]), [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn normalize_handles_zero() {
+        let zero = Quaternion::new(0.0, 0.0, 0.0, 0.0);
+        assert_eq!(zero.normalize(), zero);
+        let q = Quaternion::new(2.0, 0.0, 0.0, 0.0).normalize();
+        assert_eq!(q, Quaternion::identity());
+    }
+
+    #[test]
+    fn slerp_interpolates_angle() {
+        let a = Quaternion::identity();
+        let b = Quaternion::from_axis_angle([1.0, 1.0, 0.0], 1.0);
+        assert_same_rotation(a.slerp(&b, 0.0), a);
+        assert_same_rotation(a.slerp(&b, 1.0), b);
+        assert_same_rotation(
+            a.slerp(&b, 0.25),
+            Quaternion::from_axis_angle([1.0, 1.0, 0.0], 0.25),
+        );
+        // the same rotation with the opposite sign takes the short way too
+        assert_same_rotation(
+            a.slerp(&-b, 0.5),
+            Quaternion::from_axis_angle([1.0, 1.0, 0.0], 0.5),
+        );
+    }
+
+    #[test]
+    fn rotation_matrix_round_trip() {
+        for &(axis, angle) in [
+            ([1.0, 2.0, 3.0], 0.3),
+            ([0.0, 1.0, 0.0], 3.0),
+            ([1.0, 0.0, 0.0], -2.9),
+            ([0.0, 0.0, 1.0], 3.1),
+        ]
+        .iter()
+        {
+            let q = Quaternion::from_axis_angle(axis, angle);
+            assert_same_rotation(Quaternion::from_rotation_matrix(&q.to_rotation_matrix()), q);
+        }
+    }
+
+    #[test]
+    fn axis_angle_round_trip() {
+        let q = Quaternion::from_axis_angle([0.0, 3.0, 4.0], 1.3);
+        let (axis, angle) = q.to_axis_angle();
+        assert_close(axis, [0.0, 0.6, 0.8]);
+        assert!((angle - 1.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn euler_round_trip() {
+        let (roll, pitch, yaw) = (0.3, -0.4, 2.0);
+        let q = Quaternion::from_euler_zyx(roll, pitch, yaw);
+        let (r, p, y) = q.to_euler_zyx();
+        assert_close([r, p, y], [roll, pitch, yaw]);
+
+        let q = Quaternion::from_euler_xyz(roll, pitch, yaw);
+        let (x, y, z) = q.to_euler_xyz();
+        assert_close([x, y, z], [roll, pitch, yaw]);
+    }
+
+    #[test]
+    fn attitude_from_gravity() {
+        let q = Quaternion::from_euler_zyx(0.4, -0.7, 0.0);
+        let acc = q.rotate_inverse([0.0, 0.0, 9.8]);
+        assert_same_rotation(Quaternion::from_gravity(acc), q);
+    }
+
+    #[test]
+    fn attitude_from_gravity_and_mag() {
+        let q = Quaternion::from_euler_zyx(-0.3, 0.5, 2.5);
+        let acc = q.rotate_inverse([0.0, 0.0, 9.8]);
+        let mag = q.rotate_inverse([20.0, 0.0, -40.0]);
+        assert_same_rotation(Quaternion::from_gravity_and_mag(acc, mag), q);
+    }
+
+    #[test]
+    fn euler_zyx_matches_heading() {
+        let q = Quaternion::from_axis_angle([0.0, 0.0, 1.0], 1.0);
+        let (roll, pitch, yaw) = q.to_euler_zyx();
+        assert_close([roll, pitch, yaw], [0.0, 0.0, 1.0]);
+    }
+}
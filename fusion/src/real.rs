@@ -0,0 +1,157 @@
+use core::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+pub(crate) const DEG_TO_RAD: f32 = 0.0174533;
+
+/// Numeric type the fusion math can run on.
+pub trait Real:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+{
+    fn from_f32(v: f32) -> Self;
+
+    fn to_f32(self) -> f32;
+
+    fn sqrt(self) -> Self;
+
+    fn abs(self) -> Self;
+
+    fn zero() -> Self {
+        Self::from_f32(0.0)
+    }
+
+    fn one() -> Self {
+        Self::from_f32(1.0)
+    }
+
+    // the constants of the update, so that a type without an FPU does not convert them from
+    // f32 on every call
+
+    fn half() -> Self {
+        Self::from_f32(0.5)
+    }
+
+    fn two() -> Self {
+        Self::from_f32(2.0)
+    }
+
+    fn four() -> Self {
+        Self::from_f32(4.0)
+    }
+
+    fn deg_to_rad() -> Self {
+        Self::from_f32(DEG_TO_RAD)
+    }
+
+    /// Approximation of 1 / sqrt(self), used for normalization.
+    fn inv_sqrt(self) -> Self {
+        Self::one() / self.sqrt()
+    }
+}
+
+impl Real for f32 {
+    fn from_f32(v: f32) -> Self {
+        v
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn sqrt(self) -> Self {
+        libm::sqrtf(self)
+    }
+
+    fn abs(self) -> Self {
+        libm::fabsf(self)
+    }
+
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn one() -> Self {
+        1.0
+    }
+
+    fn half() -> Self {
+        0.5
+    }
+
+    fn two() -> Self {
+        2.0
+    }
+
+    fn four() -> Self {
+        4.0
+    }
+
+    fn deg_to_rad() -> Self {
+        DEG_TO_RAD
+    }
+
+    fn inv_sqrt(self) -> Self {
+        let half_x = 0.5_f32 * self;
+        #[repr(C)]
+        union Val {
+            f: f32,
+            i: u32,
+        }
+        let mut v = Val { f: self };
+        unsafe {
+            v.i = 0x5f3759df - (v.i >> 1);
+            v.f = v.f * (1.5f32 - half_x * v.f * v.f);
+            v.f = v.f * (1.5f32 - half_x * v.f * v.f);
+            v.f
+        }
+    }
+}
+
+impl Real for f64 {
+    fn from_f32(v: f32) -> Self {
+        v as f64
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn sqrt(self) -> Self {
+        libm::sqrt(self)
+    }
+
+    fn abs(self) -> Self {
+        libm::fabs(self)
+    }
+
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn one() -> Self {
+        1.0
+    }
+
+    fn half() -> Self {
+        0.5
+    }
+
+    fn two() -> Self {
+        2.0
+    }
+
+    fn four() -> Self {
+        4.0
+    }
+
+    fn deg_to_rad() -> Self {
+        DEG_TO_RAD as f64
+    }
+}
@@ -0,0 +1,191 @@
+//! Compact versioned byte blobs of estimator state, for warm restarts.
+//!
+//! A blob is the format version, the payload of the saved object and a Fletcher-16 checksum,
+//! so that uninitialized memory or a blob from another firmware is refused on restore. All
+//! values are stored as little endian f32 whatever the numeric type of the filter.
+
+/// Bumped whenever the layout of any payload changes.
+pub const VERSION: u8 = 3;
+
+/// Bytes a blob needs on top of the payload.
+pub const OVERHEAD: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotError {
+    /// Buffer too short for the blob.
+    Length,
+    /// Blob written by another format version.
+    Version,
+    /// Blob damaged or never written.
+    Checksum,
+}
+
+/// State that can be saved to and restored from a blob.
+pub trait Snapshot {
+    /// Payload size in bytes.
+    const SIZE: usize;
+
+    fn save(&self, w: &mut Writer);
+
+    fn load(&mut self, r: &mut Reader);
+}
+
+/// Optional state, stored as a presence flag and a payload of zeros when absent so that the
+/// blob keeps its size.
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    const SIZE: usize = 4 + T::SIZE;
+
+    fn save(&self, w: &mut Writer) {
+        match self {
+            Some(state) => {
+                w.f32(1.0);
+                state.save(w);
+            }
+            None => {
+                w.f32(0.0);
+                for _ in 0..T::SIZE / 4 {
+                    w.f32(0.0);
+                }
+            }
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) {
+        let present = r.f32() != 0.0;
+        let mut state = T::default();
+        state.load(r);
+        *self = if present { Some(state) } else { None };
+    }
+}
+
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn f32(&mut self, v: f32) {
+        self.buf[self.pos..self.pos + 4].copy_from_slice(&v.to_le_bytes());
+        self.pos += 4;
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn f32(&mut self) -> f32 {
+        let mut bytes = [0_u8; 4];
+        bytes.copy_from_slice(&self.buf[self.pos..self.pos + 4]);
+        self.pos += 4;
+        f32::from_le_bytes(bytes)
+    }
+}
+
+fn fletcher16(data: &[u8]) -> [u8; 2] {
+    let mut a = 0_u16;
+    let mut b = 0_u16;
+    for &byte in data {
+        a = (a + byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    [a as u8, b as u8]
+}
+
+/// Writes the blob of `state` to the front of `buf` and returns its length.
+pub fn save<T: Snapshot>(state: &T, buf: &mut [u8]) -> Result<usize, SnapshotError> {
+    let len = T::SIZE + OVERHEAD;
+    if buf.len() < len {
+        return Err(SnapshotError::Length);
+    }
+    buf[0] = VERSION;
+    state.save(&mut Writer {
+        buf: &mut buf[1..1 + T::SIZE],
+        pos: 0,
+    });
+    let sum = fletcher16(&buf[..1 + T::SIZE]);
+    buf[1 + T::SIZE..len].copy_from_slice(&sum);
+    Ok(len)
+}
+
+/// Restores `state` from a blob at the front of `buf`, leaving it untouched on error.
+pub fn restore<T: Snapshot>(state: &mut T, buf: &[u8]) -> Result<(), SnapshotError> {
+    let len = T::SIZE + OVERHEAD;
+    if buf.len() < len {
+        return Err(SnapshotError::Length);
+    }
+    if buf[0] != VERSION {
+        return Err(SnapshotError::Version);
+    }
+    if fletcher16(&buf[..1 + T::SIZE]) != buf[1 + T::SIZE..len] {
+        return Err(SnapshotError::Checksum);
+    }
+    state.load(&mut Reader {
+        buf: &buf[1..1 + T::SIZE],
+        pos: 0,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Pair(f32, f32);
+
+    impl Snapshot for Pair {
+        const SIZE: usize = 8;
+
+        fn save(&self, w: &mut Writer) {
+            w.f32(self.0);
+            w.f32(self.1);
+        }
+
+        fn load(&mut self, r: &mut Reader) {
+            self.0 = r.f32();
+            self.1 = r.f32();
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0_u8; 16];
+        assert_eq!(save(&Pair(1.5, -2.0), &mut buf), Ok(Pair::SIZE + OVERHEAD));
+        let mut restored = Pair::default();
+        restore(&mut restored, &buf).unwrap();
+        assert_eq!((restored.0, restored.1), (1.5, -2.0));
+    }
+
+    #[test]
+    fn optional_keeps_its_size() {
+        let mut buf = [0_u8; 16];
+        assert_eq!(save(&None::<Pair>, &mut buf), Ok(Pair::SIZE + 4 + OVERHEAD));
+        let mut restored = Some(Pair(1.0, 1.0));
+        restore(&mut restored, &buf).unwrap();
+        assert!(restored.is_none());
+
+        save(&Some(Pair(1.5, -2.0)), &mut buf).unwrap();
+        restore(&mut restored, &buf).unwrap();
+        assert!(matches!(restored, Some(Pair(x, y)) if x == 1.5 && y == -2.0));
+    }
+
+    #[test]
+    fn refuses_bad_blobs() {
+        let mut buf = [0_u8; 11];
+        let mut pair = Pair::default();
+        assert_eq!(
+            save(&Pair(1.0, 2.0), &mut buf[..10]),
+            Err(SnapshotError::Length)
+        );
+        assert_eq!(restore(&mut pair, &buf[..10]), Err(SnapshotError::Length));
+        assert_eq!(restore(&mut pair, &buf), Err(SnapshotError::Version));
+
+        save(&Pair(1.0, 2.0), &mut buf).unwrap();
+        buf[3] ^= 0x10;
+        assert_eq!(restore(&mut pair, &buf), Err(SnapshotError::Checksum));
+        assert_eq!((pair.0, pair.1), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,116 @@
+use libm::sqrtf;
+
+/// Number of samples the detector looks back over.
+pub const WINDOW: usize = 32;
+
+/// Detects rest from a window of IMU samples: the gyro norm has to stay below a threshold and
+/// the accelerometer norm must not vary more than a threshold over the whole window.
+#[derive(Clone, Copy)]
+pub struct StationaryDetector {
+    gyr_threshold: f32,
+    acc_variance_threshold: f32,
+    gyr_norms: [f32; WINDOW],
+    acc_norms: [f32; WINDOW],
+    head: usize,
+    filled: usize,
+    stationary: bool,
+}
+
+impl Default for StationaryDetector {
+    fn default() -> Self {
+        Self::new(1.0, 0.01)
+    }
+}
+
+impl StationaryDetector {
+    /// `gyr_threshold` in deg/sec, `acc_variance_threshold` in (m/s^2)^2.
+    pub fn new(gyr_threshold: f32, acc_variance_threshold: f32) -> Self {
+        Self {
+            gyr_threshold,
+            acc_variance_threshold,
+            gyr_norms: [0.0; WINDOW],
+            acc_norms: [0.0; WINDOW],
+            head: 0,
+            filled: 0,
+            stationary: false,
+        }
+    }
+
+    /// Adds a sample and returns whether the device is at rest.
+    pub fn update(&mut self, acc: [f32; 3], gyr: [f32; 3]) -> bool {
+        self.gyr_norms[self.head] = norm(gyr);
+        self.acc_norms[self.head] = norm(acc);
+        self.head = (self.head + 1) % WINDOW;
+        if self.filled < WINDOW {
+            self.filled += 1;
+        }
+
+        self.stationary = self.filled == WINDOW
+            && self.gyr_norms.iter().all(|&g| g < self.gyr_threshold)
+            && variance(&self.acc_norms) < self.acc_variance_threshold;
+        self.stationary
+    }
+
+    pub fn is_stationary(&self) -> bool {
+        self.stationary
+    }
+
+    /// Forgets the window, so that rest is only reported again after a full one.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.gyr_threshold, self.acc_variance_threshold);
+    }
+}
+
+fn norm(v: [f32; 3]) -> f32 {
+    sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2])
+}
+
+fn variance(values: &[f32]) -> f32 {
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / n;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REST_ACC: [f32; 3] = [0.1, -0.2, 9.8];
+
+    #[test]
+    fn needs_a_full_window() {
+        let mut det = StationaryDetector::default();
+        for _ in 0..WINDOW - 1 {
+            assert!(!det.update(REST_ACC, [0.1, 0.0, -0.1]));
+        }
+        assert!(det.update(REST_ACC, [0.1, 0.0, -0.1]));
+        assert!(det.is_stationary());
+
+        det.reset();
+        assert!(!det.update(REST_ACC, [0.0; 3]));
+    }
+
+    #[test]
+    fn rotation_breaks_rest() {
+        let mut det = StationaryDetector::default();
+        for _ in 0..WINDOW {
+            det.update(REST_ACC, [0.0; 3]);
+        }
+        assert!(!det.update(REST_ACC, [0.0, 5.0, 0.0]));
+        // the fast sample stays in the window
+        for _ in 0..WINDOW - 1 {
+            assert!(!det.update(REST_ACC, [0.0; 3]));
+        }
+        assert!(det.update(REST_ACC, [0.0; 3]));
+    }
+
+    #[test]
+    fn vibration_breaks_rest() {
+        let mut det = StationaryDetector::default();
+        for i in 0..WINDOW {
+            let shake = if i % 2 == 0 { 0.5 } else { -0.5 };
+            det.update([0.0, 0.0, 9.8 + shake], [0.0; 3]);
+        }
+        assert!(!det.is_stationary());
+    }
+}
@@ -0,0 +1,103 @@
+use super::quaternion::Quaternion;
+use super::snapshot::{Reader, Snapshot, Writer};
+
+/// Which part of the current orientation a tare takes as the reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TareMode {
+    /// Whole attitude, so the tared pose reads as identity.
+    Full,
+    /// Heading only, so tilt stays relative to gravity.
+    YawOnly,
+}
+
+/// Reference orientation that the filter output is expressed relative to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tare {
+    reference: Quaternion,
+}
+
+impl Tare {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Takes `q` as the zero pose from now on.
+    pub fn capture(&mut self, q: &Quaternion, mode: TareMode) {
+        self.reference = match mode {
+            TareMode::Full => q.normalize(),
+            TareMode::YawOnly => {
+                let (_, _, yaw) = q.to_euler_zyx();
+                Quaternion::from_axis_angle([0.0, 0.0, 1.0], yaw)
+            }
+        };
+    }
+
+    /// Goes back to the frame the filter started in.
+    pub fn clear(&mut self) {
+        self.reference = Quaternion::identity();
+    }
+
+    pub fn get_reference(&self) -> Quaternion {
+        self.reference
+    }
+
+    /// Orientation `q` relative to the reference.
+    pub fn apply(&self, q: &Quaternion) -> Quaternion {
+        self.reference.conjugate() * *q
+    }
+}
+
+impl Snapshot for Tare {
+    const SIZE: usize = 16;
+
+    fn save(&self, w: &mut Writer) {
+        for v in self.reference.to_array().iter() {
+            w.f32(*v);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) {
+        self.reference = Quaternion::new(r.f32(), r.f32(), r.f32(), r.f32());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn similarity(a: Quaternion, b: Quaternion) -> f32 {
+        a.dot(&b).abs()
+    }
+
+    #[test]
+    fn untared_is_passthrough() {
+        let q = Quaternion::from_euler_zyx(0.3, -0.2, 1.0);
+        assert_eq!(Tare::new().apply(&q), q);
+    }
+
+    #[test]
+    fn full_tare_zeroes_current_pose() {
+        let q = Quaternion::from_euler_zyx(0.3, -0.2, 1.0);
+        let mut tare = Tare::new();
+        tare.capture(&q, TareMode::Full);
+        assert!(similarity(tare.apply(&q), Quaternion::identity()) > 0.9999);
+
+        // later motion is seen relative to the tared pose
+        let turn = Quaternion::from_axis_angle([1.0, 0.0, 0.0], 0.5);
+        assert!(similarity(tare.apply(&(q * turn)), turn) > 0.9999);
+
+        tare.clear();
+        assert_eq!(tare.apply(&q), q);
+    }
+
+    #[test]
+    fn yaw_tare_keeps_tilt() {
+        let q = Quaternion::from_euler_zyx(0.3, -0.2, 1.0);
+        let mut tare = Tare::new();
+        tare.capture(&q, TareMode::YawOnly);
+        let (roll, pitch, yaw) = tare.apply(&q).to_euler_zyx();
+        assert!((roll - 0.3).abs() < 1e-4);
+        assert!((pitch + 0.2).abs() < 1e-4);
+        assert!(yaw.abs() < 1e-4);
+    }
+}
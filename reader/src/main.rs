@@ -9,7 +9,7 @@ use tokio::{
 };
 
 const DEVICE: &str = "/dev/ttyACM0";
-const BUF_SIZE: usize = 22;
+const BUF_SIZE: usize = 23;
 const TCP_ADDR: &str = "127.0.0.1:55555";
 
 #[tokio::main]
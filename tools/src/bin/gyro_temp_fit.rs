@@ -0,0 +1,57 @@
+//! Fits the temperature model of the gyro bias.
+//!
+//! Usage: `gyro_temp_fit [FILE]`, reading standard input without a file. The input holds one
+//! sample per line, as `temperature gx gy gz` or comma separated, with the die temperature in
+//! degrees Celsius and the raw rate in deg/s, recorded at rest while the board warms up from
+//! cold (the `gyro_temp_log` example prints them).
+
+use std::process;
+
+use fusion::calibration::GyroTempFit;
+use tools::samples;
+
+// below this the slope is mostly noise
+const MIN_SPREAD: f32 = 1.0; // degrees Celsius
+
+fn main() {
+    let samples: Vec<[f32; 4]> = samples::read_input();
+
+    let mut fit = GyroTempFit::new();
+    for [t, x, y, z] in samples.iter() {
+        fit.add(*t, [*x, *y, *z]);
+    }
+    let model = match fit.solve() {
+        Some(model) => model,
+        None => {
+            eprintln!(
+                "Could not fit {} samples, record from a cold start so that the temperature changes.",
+                samples.len()
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut sum = [0.0_f32; 3];
+    for [t, x, y, z] in samples.iter() {
+        let bias = model.bias(*t);
+        for (s, (g, b)) in sum.iter_mut().zip([*x, *y, *z].iter().zip(bias.iter())) {
+            *s += (g - b) * (g - b);
+        }
+    }
+    let residual = sum.map(|s| (s / samples.len() as f32).sqrt());
+
+    println!("samples:   {}", samples.len());
+    println!("reference: {:.2} C", model.reference);
+    println!("offset:    {:?} deg/s", model.offset);
+    println!("slope:     {:?} deg/s/C", model.slope);
+    println!("spread:    {:.2} C", fit.spread());
+    println!("residual:  {:?} deg/s rms", residual);
+    if fit.spread() < MIN_SPREAD {
+        println!("the temperature hardly changed, record again from a cold start");
+    }
+    println!();
+    println!(
+        "const GYRO_TEMP_MODEL: Option<handler::calibration::GyroTempModel> =\n    Some(handler::calibration::GyroTempModel::new({:?}, {:?}, {:?}));",
+        model.reference, model.offset, model.slope
+    );
+}
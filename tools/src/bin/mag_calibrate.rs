@@ -0,0 +1,49 @@
+//! Computes the hard- and soft-iron calibration of the magnetometer.
+//!
+//! Usage: `mag_calibrate [FILE]`, reading standard input without a file. The input holds one
+//! magnetometer sample (uT) per line, as `x y z` or `x,y,z`, recorded while moving the sensor
+//! through figure eights in its mounting.
+
+use std::process;
+
+use tools::{ellipsoid, samples};
+
+// below this the fit extrapolates over directions it has not seen
+const MIN_COVERAGE: f32 = 0.7;
+
+fn main() {
+    let samples: Vec<[f32; 3]> = samples::read_input();
+
+    let fit = match ellipsoid::fit(&samples) {
+        Some(fit) => fit,
+        None => {
+            eprintln!(
+                "Could not fit an ellipsoid to {} samples, move the sensor through more orientations.",
+                samples.len()
+            );
+            process::exit(1);
+        }
+    };
+    let cal = fit.calibration;
+    let residual = ellipsoid::residual(&cal, fit.field, &samples);
+    let coverage = ellipsoid::coverage(&cal, &samples);
+
+    println!("samples:   {}", samples.len());
+    println!("offset:    {:?} uT", cal.offset);
+    println!("soft iron: {:?}", cal.matrix);
+    println!("field:     {:.2} uT", fit.field);
+    println!(
+        "residual:  {:.3} uT rms ({:.2} %)",
+        residual,
+        residual / fit.field * 100.0
+    );
+    println!("coverage:  {:.0} %", coverage * 100.0);
+    if coverage < MIN_COVERAGE {
+        println!("coverage is low, move the sensor through more orientations and record again");
+    }
+    println!();
+    println!(
+        "const MAG_CALIBRATION: Option<handler::calibration::MagCalibration> =\n    Some(handler::calibration::MagCalibration::new({:?}, {:?}));",
+        cal.offset, cal.matrix
+    );
+}
@@ -0,0 +1,192 @@
+//! Hard- and soft-iron magnetometer calibration by fitting an ellipsoid to the samples.
+//!
+//! The samples of an undistorted magnetometer rotated through all orientations lie on a sphere.
+//! Hard iron shifts that sphere and soft iron stretches it into an ellipsoid. Fitting the
+//! quadric `x^T M x + 2 g^T x = 1` gives the centre and the shape, from which the correction
+//! maps the ellipsoid back onto a sphere whose radius is the local field strength.
+
+use fusion::calibration::MagCalibration;
+use nalgebra::{DMatrix, DVector, Matrix3, Vector3};
+
+/// Cells of equal area the unit sphere is split into for the coverage.
+const BANDS: usize = 6;
+const SECTORS: usize = 12;
+
+pub struct EllipsoidFit {
+    pub calibration: MagCalibration,
+    /// Field strength the calibrated samples are scaled to (same unit as the samples).
+    pub field: f32,
+}
+
+/// Fits an ellipsoid to the samples, failing when they are too few or too flat to define one.
+pub fn fit(samples: &[[f32; 3]]) -> Option<EllipsoidFit> {
+    if samples.len() < 9 {
+        return None;
+    }
+
+    // least squares on [x^2, y^2, z^2, 2xy, 2xz, 2yz, 2x, 2y, 2z] v = 1
+    let mut normal = DMatrix::<f64>::zeros(9, 9);
+    let mut rhs = DVector::<f64>::zeros(9);
+    for s in samples {
+        let [x, y, z] = [s[0] as f64, s[1] as f64, s[2] as f64];
+        let row = [
+            x * x,
+            y * y,
+            z * z,
+            2.0 * x * y,
+            2.0 * x * z,
+            2.0 * y * z,
+            2.0 * x,
+            2.0 * y,
+            2.0 * z,
+        ];
+        for i in 0..9 {
+            for j in 0..9 {
+                normal[(i, j)] += row[i] * row[j];
+            }
+            rhs[i] += row[i];
+        }
+    }
+    let v = normal.lu().solve(&rhs)?;
+
+    let m = Matrix3::new(v[0], v[3], v[4], v[3], v[1], v[5], v[4], v[5], v[2]);
+    let g = Vector3::new(v[6], v[7], v[8]);
+    let centre = -(m.try_inverse()? * g);
+    let r = 1.0 + (centre.transpose() * m * centre)[(0, 0)];
+
+    // (x - c)^T (M / r) (x - c) = 1
+    let eigen = (m / r).symmetric_eigen();
+    if eigen.eigenvalues.iter().any(|&l| l <= 0.0) {
+        return None;
+    }
+    let field = eigen
+        .eigenvalues
+        .iter()
+        .map(|l| 1.0 / l.sqrt())
+        .product::<f64>()
+        .cbrt();
+    let sqrt = eigen.eigenvectors
+        * Matrix3::from_diagonal(&eigen.eigenvalues.map(f64::sqrt))
+        * eigen.eigenvectors.transpose();
+    let w = sqrt * field;
+
+    let mut matrix = [[0.0_f32; 3]; 3];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = w[(i, j)] as f32;
+        }
+    }
+    Some(EllipsoidFit {
+        calibration: MagCalibration::new(
+            [centre[0] as f32, centre[1] as f32, centre[2] as f32],
+            matrix,
+        ),
+        field: field as f32,
+    })
+}
+
+/// RMS distance of the calibrated samples from the sphere of radius `field`.
+pub fn residual(calibration: &MagCalibration, field: f32, samples: &[[f32; 3]]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum = samples
+        .iter()
+        .map(|s| {
+            let [x, y, z] = calibration.apply(*s);
+            let e = (x * x + y * y + z * z).sqrt() - field;
+            e * e
+        })
+        .sum::<f32>();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// Fraction of the directions, over cells of equal area, that the calibrated samples point to.
+/// Close to 1 once the sensor has been turned through every orientation.
+pub fn coverage(calibration: &MagCalibration, samples: &[[f32; 3]]) -> f32 {
+    let mut visited = [[false; SECTORS]; BANDS];
+    for s in samples {
+        let [x, y, z] = calibration.apply(*s);
+        let norm = (x * x + y * y + z * z).sqrt();
+        if norm == 0.0 {
+            continue;
+        }
+        // bands of equal height have equal area on a sphere
+        let band = (((z / norm + 1.0) / 2.0 * BANDS as f32) as usize).min(BANDS - 1);
+        let azimuth = y.atan2(x) + std::f32::consts::PI;
+        let sector =
+            ((azimuth / (2.0 * std::f32::consts::PI) * SECTORS as f32) as usize).min(SECTORS - 1);
+        visited[band][sector] = true;
+    }
+    let count = visited.iter().flatten().filter(|&&v| v).count();
+    count as f32 / (BANDS * SECTORS) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELD: f32 = 48.0;
+
+    // directions spread over the whole sphere
+    fn sphere(count: usize) -> Vec<[f32; 3]> {
+        let golden = std::f32::consts::PI * (3.0 - 5.0_f32.sqrt());
+        (0..count)
+            .map(|i| {
+                let z = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                let r = (1.0 - z * z).sqrt();
+                let phi = golden * i as f32;
+                [FIELD * r * phi.cos(), FIELD * r * phi.sin(), FIELD * z]
+            })
+            .collect()
+    }
+
+    fn distort(v: &[f32; 3]) -> [f32; 3] {
+        let s = [[1.3, 0.1, -0.05], [0.1, 0.8, 0.02], [-0.05, 0.02, 1.1]];
+        let offset = [12.0, -30.0, 5.0];
+        let mut out = [0.0; 3];
+        for i in 0..3 {
+            out[i] = s[i][0] * v[0] + s[i][1] * v[1] + s[i][2] * v[2] + offset[i];
+        }
+        out
+    }
+
+    #[test]
+    fn recovers_offset_and_sphere() {
+        let samples: Vec<_> = sphere(500).iter().map(distort).collect();
+        let result = fit(&samples).unwrap();
+        let offset = result.calibration.offset;
+        assert!((offset[0] - 12.0).abs() < 1e-2);
+        assert!((offset[1] + 30.0).abs() < 1e-2);
+        assert!((offset[2] - 5.0).abs() < 1e-2);
+        assert!(residual(&result.calibration, result.field, &samples) < 1e-2);
+        assert!(coverage(&result.calibration, &samples) > 0.99);
+    }
+
+    #[test]
+    fn undistorted_stays_put() {
+        let samples = sphere(300);
+        let result = fit(&samples).unwrap();
+        assert!((result.field - FIELD).abs() < 1e-2);
+        for (i, row) in result.calibration.matrix.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn half_turn_is_half_covered() {
+        let samples: Vec<_> = sphere(500).into_iter().filter(|s| s[2] > 0.0).collect();
+        let coverage = coverage(&MagCalibration::default(), &samples);
+        assert!((coverage - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn refuses_degenerate_samples() {
+        assert!(fit(&sphere(5)).is_none());
+        let flat: Vec<_> = sphere(200).iter().map(|s| [s[0], s[1], 0.0]).collect();
+        assert!(fit(&flat).is_none());
+    }
+}
@@ -0,0 +1,3 @@
+//! Host side calibration of the sensors from recorded samples.
+pub mod ellipsoid;
+pub mod samples;
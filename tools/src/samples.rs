@@ -0,0 +1,63 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    process,
+};
+
+/// Reads the samples from the file named by the first argument, or from standard input
+/// without one, exiting with a message when that fails.
+pub fn read_input<const N: usize>() -> Vec<[f32; N]> {
+    match env::args().nth(1) {
+        Some(path) => match File::open(&path) {
+            Ok(f) => read(BufReader::new(f)),
+            Err(e) => {
+                eprintln!("Could not open {}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => read(io::stdin().lock()),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("Could not read the samples: {}", e);
+        process::exit(1);
+    })
+}
+
+/// Reads one sample per line, as numbers separated by commas or whitespace. Lines that do not
+/// hold exactly `N` numbers, such as headers or `#` comments, are skipped.
+pub fn read<R: BufRead, const N: usize>(input: R) -> io::Result<Vec<[f32; N]>> {
+    let mut samples = Vec::new();
+    for line in input.lines() {
+        if let Some(sample) = parse(&line?) {
+            samples.push(sample);
+        }
+    }
+    Ok(samples)
+}
+
+fn parse<const N: usize>(line: &str) -> Option<[f32; N]> {
+    let mut sample = [0.0; N];
+    let mut fields = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty());
+    for value in sample.iter_mut() {
+        *value = fields.next()?.parse().ok()?;
+    }
+    match fields.next() {
+        Some(_) => None,
+        None => Some(sample),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_headers_and_comments() {
+        let text = "# figure eight\nmx,my,mz\n1.5, -2, 3\n4 5 6\n7 8\n";
+        let samples: Vec<[f32; 3]> = read(text.as_bytes()).unwrap();
+        assert_eq!(samples, vec![[1.5, -2.0, 3.0], [4.0, 5.0, 6.0]]);
+    }
+}
@@ -66,6 +66,7 @@ pub fn put_xyz_axis(node: &mut SceneNode, scale: f32) {
 pub struct Arm {
     upper: SceneNode,
     lower: SceneNode,
+    elbow: SceneNode,
 }
 
 impl Arm {
@@ -103,7 +104,11 @@ impl Arm {
         elbow.set_color(1.0, 0.0, 1.0);
         lower_arm.set_color(0.0, 1.0, 1.0);
 
-        Arm { upper, lower }
+        Arm {
+            upper,
+            lower,
+            elbow,
+        }
     }
 
     pub fn set_upper_posture(&mut self, q: UnitQuaternion<f32>) -> &Self {
@@ -111,6 +116,16 @@ impl Arm {
         self
     }
 
+    /// Whitens the elbow while the device reports being at rest.
+    pub fn set_stationary(&mut self, stationary: bool) -> &Self {
+        if stationary {
+            self.elbow.set_color(1.0, 1.0, 1.0);
+        } else {
+            self.elbow.set_color(1.0, 0.0, 1.0);
+        }
+        self
+    }
+
     pub fn set_theta_lower(&mut self, theta: f32) -> &Self {
         self.lower
             .set_local_rotation(UnitQuaternion::from_axis_angle(&Vector3::z_axis(), theta));
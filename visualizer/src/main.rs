@@ -17,7 +17,7 @@ use nalgebra as na;
 use visualizer::graphics::*;
 
 const HEADER: [u8; 2] = [0xE0, 0xE0];
-const BUF_SIZE: usize = 22;
+const BUF_SIZE: usize = 23;
 
 fn main() {
     let listener = TcpListener::bind("0.0.0.0:55555").unwrap();
@@ -126,9 +126,11 @@ fn main() {
             let q2 = f32::from_le_bytes([data[8], data[9], data[10], data[11]]);
             let q3 = f32::from_le_bytes([data[12], data[13], data[14], data[15]]);
             let angle = f32::from_le_bytes([data[16], data[17], data[18], data[19]]);
+            let stationary = data[20] != 0;
             let rotate_q = UnitQuaternion::from_quaternion(Quaternion::new(q0, q1, q2, q3));
             arm_sim.set_upper_posture(rotate_q);
             arm_sim.set_theta_lower(angle);
+            arm_sim.set_stationary(stationary);
         }
     }
 }